@@ -5,9 +5,13 @@ VERSION=$(grep '^version' Cargo.toml | head -1 | cut -d'"' -f2)
 ARCH=$(uname -m)
 NAME="markview-${VERSION}-${ARCH}-unknown-linux-gnu"
 
+if [ ! -f data/mermaid.min.js ] || [ ! -f data/tex-svg-full.js ]; then
+    scripts/fetch-libraries.sh
+fi
+
 mkdir -p "$NAME"
 cargo build --release
 cp target/release/gtk-markdown-viewer "$NAME/markview"
-cp LICENSE README.md "$NAME/"
+cp LICENSE README.md data/mermaid.min.js data/tex-svg-full.js "$NAME/"
 tar czvf "${NAME}.tar.gz" "$NAME"
 echo "Built ${NAME}.tar.gz"
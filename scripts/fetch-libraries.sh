@@ -0,0 +1,53 @@
+#!/usr/bin/env bash
+# Downloads the libraries that draw diagrams (Mermaid) and typeset math
+# (MathJax) in the preview and in exports into data/, where development
+# builds and release archives pick them up. Nothing is fetched at run time.
+#
+# Each file is checked against a pinned SHA-256. The Flatpak manifest
+# downloads the same URLs with the same checksums. After bumping a version,
+# run with --pin to record the new checksums in both places, and review the
+# diff before committing it.
+set -e
+
+MERMAID_VERSION=11.4.1
+MERMAID_SHA256=0000000000000000000000000000000000000000000000000000000000000000
+MATHJAX_VERSION=3.2.2
+MATHJAX_SHA256=0000000000000000000000000000000000000000000000000000000000000000
+
+SCRIPT=$(realpath "$0")
+cd "$(dirname "$0")/.."
+MANIFEST=flatpak/io.github.v8v88v8v88.MarkView.yml
+PIN=false
+if [ "$1" = --pin ]; then
+    PIN=true
+fi
+
+TMP=$(mktemp -d)
+trap 'rm -rf "$TMP"' EXIT
+
+# fetch VARIABLE URL: installs the file at URL into data/ if its checksum is
+# the one in VARIABLE, or records its checksum there with --pin.
+fetch() {
+    local name expected actual
+    name=$(basename "$2")
+    expected=${!1}
+    curl -fsSL "$2" -o "$TMP/$name"
+    actual=$(sha256sum "$TMP/$name" | cut -d' ' -f1)
+    if $PIN; then
+        sed -i "s/^$1=.*/$1=$actual/" "$SCRIPT"
+        # The sha256 entry follows the URL in the manifest.
+        sed -i "\|url: $2\$|{n;s/sha256: .*/sha256: '$actual'/}" "$MANIFEST"
+        echo "Pinned $name at $actual"
+    elif [ "$actual" != "$expected" ]; then
+        echo "Checksum mismatch for $2" >&2
+        echo "  expected $expected" >&2
+        echo "  got      $actual" >&2
+        echo "Run $0 --pin after a version bump to record the new checksum." >&2
+        exit 1
+    fi
+    install -Dm644 "$TMP/$name" "data/$name"
+    echo "Installed data/$name"
+}
+
+fetch MERMAID_SHA256 "https://cdn.jsdelivr.net/npm/mermaid@$MERMAID_VERSION/dist/mermaid.min.js"
+fetch MATHJAX_SHA256 "https://cdn.jsdelivr.net/npm/mathjax@$MATHJAX_VERSION/es5/tex-svg-full.js"
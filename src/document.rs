@@ -0,0 +1,1088 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Duration;
+
+use adw::{prelude::BinExt, StyleManager};
+use gtk4::prelude::*;
+use gtk4::{gio, glib, EventControllerKey, Orientation, Paned, PropagationPhase, ScrolledWindow};
+use sourceview5::{prelude::*, Buffer as SourceBuffer, View as SourceView, VimIMContext};
+use webkit6::prelude::*;
+use webkit6::{LoadEvent, NavigationPolicyDecision, NavigationType, PolicyDecisionType, WebView};
+
+use crate::front_matter::{self, FrontMatter, Value};
+use crate::highlight::{PendingBlock, highlight_cached_code_blocks, highlight_pending};
+use crate::prefs::{Preferences, DEFAULT_SCHEME};
+use crate::preview::{
+    background_color, base_uri_for_preview, build_preview_page, outline, render_markdown,
+    theme_css, Heading, PLACEHOLDER_HTML,
+};
+use crate::recovery::{self, Snapshot};
+use crate::search::{EditorSearch, PreviewSearch};
+
+pub const DEFAULT_PANED_POSITION: i32 = 400;
+/// Mark used to scroll the editor to a restored position once it is laid out.
+const RESTORE_MARK: &str = "markview-restore";
+/// Quiet period after the last edit before the preview is re-rendered.
+const RENDER_DELAY: Duration = Duration::from_millis(150);
+
+/// One open Markdown document: its editor, preview and backing file.
+/// The box returned by `widget()` is what gets hosted in a tab.
+pub struct Document {
+    root: gtk4::Box,
+    paned: Paned,
+    /// Collapsible outline panel to the left of the panes.
+    outline_revealer: gtk4::Revealer,
+    outline_list: gtk4::ListBox,
+    /// Headings shown in `outline_list`, one per row.
+    headings: RefCell<Vec<Heading>>,
+    /// The editor with its find and replace bar.
+    editor_pane: gtk4::Box,
+    editor_search: Rc<EditorSearch>,
+    /// Switches between the preview and the welcome screen.
+    preview_stack: gtk4::Stack,
+    /// Welcome screen shown instead of the preview while the document is blank.
+    welcome: adw::Bin,
+    preview_search: Rc<PreviewSearch>,
+    /// Shown when the file changes on disk while there are unsaved edits.
+    reload_banner: gtk4::ActionBar,
+    monitor: RefCell<Option<gio::FileMonitor>>,
+    view: SourceView,
+    buffer: SourceBuffer,
+    webview: WebView,
+    file: RefCell<Option<gio::File>>,
+    /// Entity tag of the file as last loaded or saved, used to detect changes
+    /// made by other programs before overwriting them.
+    etag: RefCell<Option<String>>,
+    /// Last rendered Markdown body; re-used when only the theme changes.
+    html: RefCell<String>,
+    /// Bumped for every render request so stale worker results can be dropped.
+    render_generation: Cell<u64>,
+    render_source: RefCell<Option<glib::SourceId>>,
+    /// Title from the front matter, taken from the render worker so edits
+    /// do not parse the buffer on the main thread.
+    front_matter_title: RefCell<Option<String>>,
+    title_handlers: RefCell<Vec<Box<dyn Fn()>>>,
+    /// Base URI the preview page was loaded with; `None` until the first load
+    /// or when the page must be rebuilt.
+    page_base_uri: RefCell<Option<Option<String>>>,
+    /// Whether the bootstrap script is available for in-place updates.
+    page_ready: Cell<bool>,
+    saved_paned_pos: Cell<i32>,
+    editor_visible: Cell<bool>,
+    viewer_mode: Cell<bool>,
+    /// Set while the editor is scrolled to follow the preview, so that the
+    /// resulting adjustment change is not echoed back.
+    syncing_editor: Cell<bool>,
+    vim_controller: RefCell<Option<EventControllerKey>>,
+    /// Preference change handlers, disconnected when the document goes away.
+    prefs_handlers: RefCell<Vec<glib::SignalHandlerId>>,
+    /// Name of this document's crash-recovery snapshot.
+    recovery_id: RefCell<String>,
+    /// Whether the buffer changed since the last snapshot was written.
+    snapshot_pending: Cell<bool>,
+}
+
+impl Document {
+    pub fn new() -> Rc<Self> {
+        let source_view = SourceView::new();
+        let source_buffer: SourceBuffer = source_view.buffer().downcast().unwrap();
+        source_buffer.set_language(Some(
+            &sourceview5::LanguageManager::default()
+                .language("markdown")
+                .unwrap(),
+        ));
+        source_buffer.set_highlight_syntax(true);
+        source_view.set_monospace(true);
+        source_view.set_tab_width(4);
+        source_view.set_auto_indent(true);
+        source_view.set_indent_on_tab(true);
+        source_view.set_smart_backspace(true);
+        source_view.set_top_margin(8);
+        source_view.set_bottom_margin(8);
+        source_view.set_left_margin(8);
+        source_view.set_right_margin(8);
+
+        let editor_scroll = ScrolledWindow::builder()
+            .child(&source_view)
+            .vexpand(true)
+            .hexpand(true)
+            .build();
+
+        let editor_search = EditorSearch::new(&source_view);
+        let editor_pane = gtk4::Box::new(Orientation::Vertical, 0);
+        editor_pane.append(editor_search.widget());
+        editor_pane.append(&editor_scroll);
+
+        let webview = WebView::new();
+        webview.set_vexpand(true);
+        webview.set_hexpand(true);
+
+        let preview_scroll = ScrolledWindow::builder()
+            .child(&webview)
+            .vexpand(true)
+            .hexpand(true)
+            .build();
+
+        let preview_search = PreviewSearch::new(&webview);
+
+        let preview_box = gtk4::Box::new(Orientation::Vertical, 0);
+        preview_box.append(preview_search.widget());
+        preview_box.append(&preview_scroll);
+
+        let welcome = adw::Bin::new();
+        let preview_stack = gtk4::Stack::new();
+        preview_stack.add_named(&preview_box, Some("preview"));
+        preview_stack.add_named(&welcome, Some("welcome"));
+
+        let paned = Paned::builder()
+            .orientation(Orientation::Horizontal)
+            .vexpand(true)
+            .hexpand(true)
+            .build();
+        paned.set_start_child(Some(&editor_pane));
+        paned.set_end_child(Some(&preview_stack));
+        paned.set_shrink_start_child(true);
+        paned.set_position(DEFAULT_PANED_POSITION);
+
+        let outline_list = gtk4::ListBox::new();
+        outline_list.add_css_class("navigation-sidebar");
+        outline_list.set_placeholder(Some(
+            &gtk4::Label::builder()
+                .label("No headings")
+                .css_classes(["dim-label"])
+                .margin_top(12)
+                .build(),
+        ));
+        let outline_scroll = ScrolledWindow::builder()
+            .child(&outline_list)
+            .hscrollbar_policy(gtk4::PolicyType::Never)
+            .width_request(220)
+            .vexpand(true)
+            .build();
+        let outline_box = gtk4::Box::new(Orientation::Horizontal, 0);
+        outline_box.append(&outline_scroll);
+        outline_box.append(&gtk4::Separator::new(Orientation::Vertical));
+        let outline_revealer = gtk4::Revealer::builder()
+            .child(&outline_box)
+            .transition_type(gtk4::RevealerTransitionType::SlideRight)
+            .build();
+
+        let body = gtk4::Box::new(Orientation::Horizontal, 0);
+        body.append(&outline_revealer);
+        body.append(&paned);
+
+        // A bar rather than an adw::Banner, which only has room for one
+        // button.
+        let reload_banner = gtk4::ActionBar::builder().revealed(false).build();
+        reload_banner.pack_start(
+            &gtk4::Label::builder()
+                .label("The file has changed on disk. Reload it, or keep your version.")
+                .wrap(true)
+                .xalign(0.0)
+                .build(),
+        );
+        let reload_button = gtk4::Button::with_label("Reload");
+        reload_button.add_css_class("suggested-action");
+        // Keep Mine only hides the banner; saving still asks before
+        // overwriting the newer file.
+        let keep_button = gtk4::Button::with_label("Keep Mine");
+        reload_banner.pack_end(&reload_button);
+        reload_banner.pack_end(&keep_button);
+
+        let root = gtk4::Box::new(Orientation::Vertical, 0);
+        root.append(&reload_banner);
+        root.append(&body);
+
+        let doc = Rc::new(Self {
+            root,
+            paned,
+            outline_revealer,
+            outline_list,
+            headings: RefCell::new(Vec::new()),
+            editor_pane,
+            editor_search,
+            preview_stack,
+            welcome,
+            preview_search,
+            reload_banner,
+            monitor: RefCell::new(None),
+            view: source_view,
+            buffer: source_buffer,
+            webview,
+            file: RefCell::new(None),
+            etag: RefCell::new(None),
+            html: RefCell::new(String::new()),
+            render_generation: Cell::new(0),
+            render_source: RefCell::new(None),
+            front_matter_title: RefCell::new(None),
+            title_handlers: RefCell::new(Vec::new()),
+            page_base_uri: RefCell::new(None),
+            page_ready: Cell::new(false),
+            saved_paned_pos: Cell::new(DEFAULT_PANED_POSITION),
+            editor_visible: Cell::new(true),
+            viewer_mode: Cell::new(false),
+            syncing_editor: Cell::new(false),
+            vim_controller: RefCell::new(None),
+            prefs_handlers: RefCell::new(Vec::new()),
+            recovery_id: RefCell::new(recovery::new_id()),
+            snapshot_pending: Cell::new(false),
+        });
+        doc.bind_preferences();
+
+        let weak = Rc::downgrade(&doc);
+        doc.webview.connect_load_changed(move |_, event| {
+            let Some(doc) = weak.upgrade() else {
+                return;
+            };
+            match event {
+                LoadEvent::Started => doc.page_ready.set(false),
+                LoadEvent::Finished => {
+                    doc.page_ready.set(true);
+                    // Catch up on anything rendered while the page was loading.
+                    doc.refresh_preview();
+                    doc.refresh_theme();
+                }
+                _ => {}
+            }
+        });
+        // Links open in the browser. Following them in the preview would
+        // leave later renders running against the linked page.
+        doc.webview.connect_decide_policy(|webview, decision, decision_type| {
+            if !matches!(
+                decision_type,
+                PolicyDecisionType::NavigationAction | PolicyDecisionType::NewWindowAction
+            ) {
+                return false;
+            }
+            let Some(mut action) = decision
+                .downcast_ref::<NavigationPolicyDecision>()
+                .and_then(|decision| decision.navigation_action())
+            else {
+                return false;
+            };
+            // The page loaded by `load_html`, and frames embedded in it.
+            if action.navigation_type() == NavigationType::Other && !action.is_user_gesture() {
+                return false;
+            }
+            let Some(uri) = action.request().and_then(|request| request.uri()) else {
+                return false;
+            };
+            // Links to anchors just scroll the preview.
+            if decision_type == PolicyDecisionType::NavigationAction
+                && let Some((page, _)) = uri.split_once('#')
+                && webview.uri().is_some_and(|current| current.split('#').next() == Some(page))
+            {
+                return false;
+            }
+            decision.ignore();
+            gtk4::UriLauncher::new(&uri).launch(
+                webview.root().and_downcast_ref::<gtk4::Window>(),
+                None::<&gio::Cancellable>,
+                |result| {
+                    if let Err(e) = result {
+                        eprintln!("Failed to open link: {e}");
+                    }
+                },
+            );
+            true
+        });
+        doc.refresh_preview();
+
+        // --- Synchronized scrolling ---
+        if let Some(adj) = doc.view.vadjustment() {
+            let weak = Rc::downgrade(&doc);
+            adj.connect_value_changed(move |_| {
+                if let Some(doc) = weak.upgrade() {
+                    doc.sync_preview_scroll();
+                }
+            });
+        }
+        if let Some(ucm) = doc.webview.user_content_manager() {
+            ucm.register_script_message_handler("markviewScroll", None);
+            let weak = Rc::downgrade(&doc);
+            ucm.connect_script_message_received(Some("markviewScroll"), move |_, value| {
+                if let Some(doc) = weak.upgrade() {
+                    let line = value.to_double();
+                    doc.select_section(if line < 0.0 { i32::MAX } else { line as i32 });
+                    doc.sync_editor_scroll(line);
+                }
+            });
+            ucm.register_script_message_handler("markviewJump", None);
+            let weak = Rc::downgrade(&doc);
+            ucm.connect_script_message_received(Some("markviewJump"), move |_, value| {
+                if let Some(doc) = weak.upgrade() {
+                    doc.goto_line(value.to_int32());
+                }
+            });
+        }
+
+        let weak = Rc::downgrade(&doc);
+        reload_button.connect_clicked(move |_| {
+            if let Some(doc) = weak.upgrade() {
+                doc.reload();
+            }
+        });
+        let weak = Rc::downgrade(&doc);
+        keep_button.connect_clicked(move |_| {
+            if let Some(doc) = weak.upgrade() {
+                doc.reload_banner.set_revealed(false);
+            }
+        });
+
+        // --- Outline ---
+        let weak = Rc::downgrade(&doc);
+        doc.outline_list.connect_row_activated(move |_, row| {
+            if let Some(doc) = weak.upgrade() {
+                doc.scroll_to_heading(row.index());
+            }
+        });
+        let weak = Rc::downgrade(&doc);
+        doc.buffer.connect_cursor_position_notify(move |buffer| {
+            if let Some(doc) = weak.upgrade() {
+                doc.select_section(buffer.iter_at_mark(&buffer.get_insert()).line());
+            }
+        });
+
+        // --- Live Preview ---
+        let weak = Rc::downgrade(&doc);
+        doc.buffer.connect_changed(move |_| {
+            if let Some(doc) = weak.upgrade() {
+                doc.snapshot_pending.set(true);
+                doc.update_welcome();
+                doc.queue_render();
+            }
+        });
+        // Undoing back to the saved text leaves nothing to recover, and a
+        // clean close would otherwise leave the snapshot behind.
+        let weak = Rc::downgrade(&doc);
+        doc.buffer.connect_modified_changed(move |buffer| {
+            if !buffer.is_modified()
+                && let Some(doc) = weak.upgrade()
+            {
+                doc.discard_snapshot();
+            }
+        });
+
+        doc
+    }
+
+    pub fn widget(&self) -> &gtk4::Box {
+        &self.root
+    }
+
+    pub fn buffer(&self) -> &SourceBuffer {
+        &self.buffer
+    }
+
+    pub fn file(&self) -> Option<gio::File> {
+        self.file.borrow().clone()
+    }
+
+    pub fn set_file(self: &Rc<Self>, file: Option<gio::File>) {
+        *self.file.borrow_mut() = file;
+        self.watch_file();
+        self.update_welcome();
+        // Relative links and images resolve against the file's directory.
+        self.refresh_preview();
+    }
+
+    /// Display name for tabs, and for the window title when the front matter
+    /// has no title.
+    pub fn title(&self) -> String {
+        self.file
+            .borrow()
+            .as_ref()
+            .and_then(|f| f.basename())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "Untitled".to_string())
+    }
+
+    /// Whether the buffer has edits that were not written to disk.
+    pub fn is_modified(&self) -> bool {
+        self.buffer.is_modified()
+    }
+
+    /// Marks the buffer as matching what is on disk, e.g. after a save.
+    pub fn set_saved(&self) {
+        self.buffer.set_modified(false);
+        self.reload_banner.set_revealed(false);
+        self.discard_snapshot();
+    }
+
+    /// An untitled, empty document that opening a file may replace.
+    pub fn is_blank(&self) -> bool {
+        self.file.borrow().is_none() && self.buffer.char_count() == 0
+    }
+
+    pub fn text(&self) -> glib::GString {
+        self.buffer
+            .text(&self.buffer.start_iter(), &self.buffer.end_iter(), false)
+    }
+
+    /// Properties from the front matter.
+    pub fn front_matter(&self) -> Option<FrontMatter> {
+        front_matter::parse(&self.text())
+    }
+
+    /// Front matter title as of the last render.
+    pub fn front_matter_title(&self) -> Option<String> {
+        self.front_matter_title.borrow().clone()
+    }
+
+    /// Calls `f` when a render finds a different front matter title.
+    pub fn connect_front_matter_title_changed(&self, f: impl Fn() + 'static) {
+        self.title_handlers.borrow_mut().push(Box::new(f));
+    }
+
+    /// Sets front matter fields as one undoable edit, adding a front matter
+    /// block if there is none. Empty values remove their field.
+    pub fn set_front_matter(&self, changes: &[(String, Value)]) {
+        let text = self.text();
+        let (range, replacement) = front_matter::update(&text, changes);
+        if text[range.clone()] == replacement {
+            return;
+        }
+        let offset = |byte: usize| text[..byte].chars().count() as i32;
+        let mut start = self.buffer.iter_at_offset(offset(range.start));
+        let mut end = self.buffer.iter_at_offset(offset(range.end));
+        self.buffer.begin_user_action();
+        self.buffer.delete(&mut start, &mut end);
+        self.buffer.insert(&mut start, &replacement);
+        self.buffer.end_user_action();
+    }
+
+    pub fn load(self: &Rc<Self>, file: gio::File) -> Result<(), String> {
+        let (content, etag) = read_file(&file)?;
+        *self.file.borrow_mut() = Some(file);
+        self.watch_file();
+        self.replace_text(&content, etag);
+        self.update_welcome();
+        Ok(())
+    }
+
+    /// Sets the welcome screen shown in place of the preview while the
+    /// document is blank.
+    pub fn set_welcome(&self, content: Option<&gtk4::Widget>) {
+        self.welcome.set_child(content);
+        self.update_welcome();
+    }
+
+    fn update_welcome(&self) {
+        let show = self.is_blank() && self.welcome.child().is_some();
+        self.preview_stack
+            .set_visible_child_name(if show { "welcome" } else { "preview" });
+    }
+
+    /// Writes the buffer to `file` atomically: GIO writes a temporary file and
+    /// renames it over the target, so a failed save never truncates it.
+    ///
+    /// With `check_etag`, the save fails with `gio::IOErrorEnum::WrongEtag` if
+    /// the file changed on disk since it was loaded or last saved. `backup`
+    /// keeps the previous version as `<name>~`.
+    pub fn save_to(&self, file: &gio::File, check_etag: bool, backup: bool) -> Result<(), glib::Error> {
+        let etag = if check_etag { self.etag.borrow().clone() } else { None };
+        let new_etag = file.replace_contents(
+            self.text().as_bytes(),
+            etag.as_deref(),
+            backup,
+            gio::FileCreateFlags::NONE,
+            None::<&gio::Cancellable>,
+        )?;
+        self.etag.replace(new_etag.map(|e| e.to_string()));
+        self.set_saved();
+        Ok(())
+    }
+
+    /// Writes a recovery snapshot if the buffer changed since the last one, or
+    /// drops it once there is nothing left to recover.
+    pub fn write_snapshot(&self) {
+        if !self.is_modified() {
+            self.discard_snapshot();
+            return;
+        }
+        if !self.snapshot_pending.replace(false) {
+            return;
+        }
+        let cursor = self.buffer.iter_at_mark(&self.buffer.get_insert());
+        let snapshot = Snapshot {
+            id: self.recovery_id.borrow().clone(),
+            uri: self.file().map(|f| f.uri().to_string()),
+            cursor_line: cursor.line(),
+            cursor_column: cursor.line_offset(),
+            text: self.text().to_string(),
+        };
+        if let Err(e) = recovery::write(&snapshot) {
+            eprintln!("Failed to write recovery snapshot: {e}");
+            self.snapshot_pending.set(true);
+        }
+    }
+
+    /// Removes the recovery snapshot, e.g. after a save or when the user
+    /// chose to throw the changes away.
+    pub fn discard_snapshot(&self) {
+        self.snapshot_pending.set(false);
+        recovery::remove(&self.recovery_id.borrow());
+    }
+
+    /// Restores unsaved text from a recovery snapshot. The document stays
+    /// modified and keeps updating the same snapshot until it is saved.
+    pub fn restore(self: &Rc<Self>, snapshot: Snapshot) {
+        self.recovery_id.replace(snapshot.id);
+        *self.file.borrow_mut() = snapshot.uri.map(|uri| gio::File::for_uri(&uri));
+        self.watch_file();
+        self.update_welcome();
+        self.etag.replace(None);
+        self.buffer.begin_irreversible_action();
+        self.buffer.set_text(&snapshot.text);
+        self.buffer.end_irreversible_action();
+        self.buffer.set_modified(true);
+        self.snapshot_pending.set(false);
+        if let Some(iter) = self
+            .buffer
+            .iter_at_line_offset(snapshot.cursor_line, snapshot.cursor_column)
+        {
+            self.buffer.place_cursor(&iter);
+        }
+        self.render();
+    }
+
+    /// Replaces the buffer with `content` from disk, keeping the cursor on the
+    /// same line where possible.
+    fn replace_text(self: &Rc<Self>, content: &str, etag: Option<String>) {
+        self.etag.replace(etag);
+        let line = self.buffer.iter_at_mark(&self.buffer.get_insert()).line();
+        // Loading a file is not something the user should be able to undo.
+        self.buffer.begin_irreversible_action();
+        self.buffer.set_text(content);
+        self.buffer.end_irreversible_action();
+        self.buffer.set_modified(false);
+        if let Some(iter) = self.buffer.iter_at_line(line) {
+            self.buffer.place_cursor(&iter);
+        }
+        self.reload_banner.set_revealed(false);
+        // Show the new text right away rather than after the edit debounce.
+        self.render();
+    }
+
+    /// Re-reads the file from disk, discarding unsaved edits.
+    pub fn reload(self: &Rc<Self>) {
+        let Some(file) = self.file() else {
+            return;
+        };
+        match read_file(&file) {
+            Ok((content, etag)) => self.replace_text(&content, etag),
+            Err(e) => eprintln!("Failed to reload file: {e}"),
+        }
+    }
+
+    /// Starts watching the current file for changes made by other programs.
+    fn watch_file(self: &Rc<Self>) {
+        if let Some(monitor) = self.monitor.take() {
+            monitor.cancel();
+        }
+        self.reload_banner.set_revealed(false);
+        let Some(file) = self.file() else {
+            return;
+        };
+        let monitor = match file.monitor_file(gio::FileMonitorFlags::NONE, None::<&gio::Cancellable>) {
+            Ok(monitor) => monitor,
+            Err(e) => {
+                eprintln!("Failed to watch {}: {e}", file.uri());
+                return;
+            }
+        };
+        let weak = Rc::downgrade(self);
+        monitor.connect_changed(move |_, _, _, event| {
+            // Editors that save atomically show up as a new file being created.
+            if matches!(
+                event,
+                gio::FileMonitorEvent::ChangesDoneHint | gio::FileMonitorEvent::Created
+            ) && let Some(doc) = weak.upgrade()
+            {
+                doc.file_changed_on_disk();
+            }
+        });
+        self.monitor.replace(Some(monitor));
+    }
+
+    fn file_changed_on_disk(self: &Rc<Self>) {
+        let Some(file) = self.file() else {
+            return;
+        };
+        let Ok((content, etag)) = read_file(&file) else {
+            return;
+        };
+        // Our own saves come back through the monitor too.
+        if content == self.text().as_str() {
+            self.etag.replace(etag);
+            self.reload_banner.set_revealed(false);
+            return;
+        }
+        if self.is_modified() {
+            self.reload_banner.set_revealed(true);
+        } else {
+            self.replace_text(&content, etag);
+        }
+    }
+
+    /// Coalesces edits: the preview is rendered once typing pauses for
+    /// `RENDER_DELAY`.
+    fn queue_render(self: &Rc<Self>) {
+        if let Some(id) = self.render_source.take() {
+            id.remove();
+        }
+        let weak = Rc::downgrade(self);
+        let id = glib::timeout_add_local_once(RENDER_DELAY, move || {
+            if let Some(doc) = weak.upgrade() {
+                doc.render_source.replace(None);
+                doc.render();
+            }
+        });
+        self.render_source.replace(Some(id));
+    }
+
+    /// Parses the buffer on a worker thread and shows the result, unless a
+    /// newer render was requested in the meantime.
+    fn render(self: &Rc<Self>) {
+        if let Some(id) = self.render_source.take() {
+            id.remove();
+        }
+        let generation = self.render_generation.get() + 1;
+        self.render_generation.set(generation);
+        let text = self.text().to_string();
+        let math = Preferences::default().math();
+        let weak = Rc::downgrade(self);
+        glib::spawn_future_local(async move {
+            let Ok((html_out, headings, title)) = gio::spawn_blocking(move || {
+                let title = front_matter::parse(&text).and_then(|front_matter| front_matter.title());
+                (render_markdown(&text, true, math), outline(&text, math), title)
+            })
+            .await
+            else {
+                eprintln!("Markdown render worker panicked");
+                return;
+            };
+            if let Some(doc) = weak.upgrade()
+                && doc.render_generation.get() == generation
+            {
+                let (highlighted, pending) =
+                    highlight_cached_code_blocks(&html_out, &Preferences::default().color_scheme());
+                doc.html.replace(highlighted);
+                doc.refresh_preview();
+                doc.set_outline(headings);
+                if *doc.front_matter_title.borrow() != title {
+                    doc.front_matter_title.replace(title);
+                    for handler in doc.title_handlers.borrow().iter() {
+                        handler();
+                    }
+                }
+                if !pending.is_empty() {
+                    doc.highlight_later(html_out, pending, generation);
+                }
+            }
+        });
+    }
+
+    /// Highlights the code blocks `render` found no cached colors for, one
+    /// per idle iteration so typing stays responsive, then shows them.
+    fn highlight_later(self: &Rc<Self>, html_out: String, mut pending: Vec<PendingBlock>, generation: u64) {
+        let weak = Rc::downgrade(self);
+        glib::idle_add_local(move || {
+            let Some(doc) = weak.upgrade() else {
+                return glib::ControlFlow::Break;
+            };
+            if doc.render_generation.get() != generation {
+                return glib::ControlFlow::Break;
+            }
+            if highlight_pending(&mut pending) {
+                return glib::ControlFlow::Continue;
+            }
+            let (highlighted, _) = highlight_cached_code_blocks(&html_out, &Preferences::default().color_scheme());
+            doc.html.replace(highlighted);
+            doc.refresh_preview();
+            glib::ControlFlow::Break
+        });
+    }
+
+    /// Shows the last rendered body. The page is only (re)loaded when the base
+    /// URI changes; otherwise the body is patched in place.
+    pub fn refresh_preview(&self) {
+        let html_out = self.html.borrow();
+        let body = if html_out.is_empty() {
+            PLACEHOLDER_HTML
+        } else {
+            html_out.as_str()
+        };
+        let base_uri = base_uri_for_preview(self.file.borrow().as_ref());
+        if self.page_base_uri.borrow().as_ref() != Some(&base_uri) {
+            let dark = StyleManager::default().is_dark();
+            self.webview
+                .load_html(&build_preview_page(body, dark, Preferences::default().math()), base_uri.as_deref());
+            self.webview.set_background_color(&background_color(dark));
+            self.page_base_uri.replace(Some(base_uri));
+            return;
+        }
+        let args = glib::VariantDict::new(None);
+        args.insert("html", body);
+        self.run_preview_script("markview.setContent(html);", &args);
+    }
+
+    /// Switches the preview stylesheet to match the light/dark appearance.
+    pub fn refresh_theme(&self) {
+        let dark = StyleManager::default().is_dark();
+        self.webview.set_background_color(&background_color(dark));
+        let args = glib::VariantDict::new(None);
+        args.insert("css", theme_css(dark));
+        args.insert("dark", dark);
+        self.run_preview_script("markview.setTheme(css, dark);", &args);
+    }
+
+    /// Calls into the preview page's bootstrap script. Skipped while the page
+    /// is loading; the load handler re-syncs once it finishes.
+    fn run_preview_script(&self, body: &str, args: &glib::VariantDict) {
+        if !self.page_ready.get() {
+            return;
+        }
+        self.webview.call_async_javascript_function(
+            body,
+            Some(&args.end()),
+            None,
+            None,
+            None::<&gio::Cancellable>,
+            |result| {
+                if let Err(e) = result {
+                    eprintln!("Preview script failed: {e}");
+                }
+            },
+        );
+    }
+
+    /// Fractional source line at the top of the editor viewport, or `-1.0`
+    /// when scrolled to the very end.
+    fn editor_top_line(&self) -> f64 {
+        let Some(adj) = self.view.vadjustment() else {
+            return 0.0;
+        };
+        if adj.value() >= adj.upper() - adj.page_size() - 1.0 && adj.value() > 0.0 {
+            return -1.0;
+        }
+        let rect = self.view.visible_rect();
+        let (iter, line_top) = self.view.line_at_y(rect.y());
+        let (_, height) = self.view.line_yrange(&iter);
+        let frac = if height > 0 {
+            f64::from(rect.y() - line_top) / f64::from(height)
+        } else {
+            0.0
+        };
+        f64::from(iter.line()) + frac.clamp(0.0, 1.0)
+    }
+
+    fn sync_preview_scroll(&self) {
+        if !Preferences::default().sync_scroll() || self.syncing_editor.get() {
+            return;
+        }
+        let args = glib::VariantDict::new(None);
+        args.insert("line", self.editor_top_line());
+        self.run_preview_script("markview.scrollToLine(line);", &args);
+    }
+
+    /// Scrolls the editor so fractional source `line` is at the top; a
+    /// negative line scrolls to the end.
+    fn sync_editor_scroll(&self, line: f64) {
+        if !Preferences::default().sync_scroll() {
+            return;
+        }
+        let Some(adj) = self.view.vadjustment() else {
+            return;
+        };
+        let value = if line < 0.0 {
+            adj.upper() - adj.page_size()
+        } else {
+            let Some(iter) = self.buffer.iter_at_line(line.floor() as i32) else {
+                return;
+            };
+            let (y, height) = self.view.line_yrange(&iter);
+            f64::from(y) + line.fract() * f64::from(height)
+        };
+        self.syncing_editor.set(true);
+        adj.set_value(value);
+        self.syncing_editor.set(false);
+    }
+
+    /// Places the cursor at the start of 0-based `line` and scrolls it into view.
+    pub fn goto_line(&self, line: i32) {
+        let Some(iter) = self.buffer.iter_at_line(line) else {
+            return;
+        };
+        self.buffer.place_cursor(&iter);
+        self.view
+            .scroll_to_mark(&self.buffer.get_insert(), 0.0, true, 0.0, 0.3);
+        self.view.grab_focus();
+    }
+
+    /// Rebuilds the outline panel when the headings changed.
+    fn set_outline(&self, headings: Vec<Heading>) {
+        if *self.headings.borrow() == headings {
+            return;
+        }
+        while let Some(row) = self.outline_list.first_child() {
+            self.outline_list.remove(&row);
+        }
+        for heading in &headings {
+            let label = gtk4::Label::builder()
+                .label(&heading.title)
+                .xalign(0.0)
+                .ellipsize(gtk4::pango::EllipsizeMode::End)
+                .tooltip_text(&heading.title)
+                .margin_start(12 * (heading.level as i32 - 1))
+                .build();
+            if heading.level == 1 {
+                label.add_css_class("heading");
+            }
+            self.outline_list.append(&label);
+        }
+        self.headings.replace(headings);
+        let (line, _) = self.cursor_position();
+        self.select_section(line);
+    }
+
+    /// Highlights the outline entry of the section containing 0-based `line`.
+    fn select_section(&self, line: i32) {
+        let index = self.headings.borrow().iter().rposition(|h| h.line <= line);
+        match index.and_then(|i| self.outline_list.row_at_index(i as i32)) {
+            Some(row) => self.outline_list.select_row(Some(&row)),
+            None => self.outline_list.unselect_all(),
+        }
+    }
+
+    /// Moves the cursor to the heading at `index` in the outline and scrolls
+    /// both panes so it is at the top.
+    fn scroll_to_heading(&self, index: i32) {
+        let Some(line) = self.headings.borrow().get(index as usize).map(|h| h.line) else {
+            return;
+        };
+        let Some(iter) = self.buffer.iter_at_line(line) else {
+            return;
+        };
+        self.buffer.place_cursor(&iter);
+        self.view
+            .scroll_to_mark(&self.buffer.get_insert(), 0.0, true, 0.0, 0.0);
+        let args = glib::VariantDict::new(None);
+        args.insert("line", f64::from(line));
+        self.run_preview_script("markview.scrollToLine(line);", &args);
+        self.grab_focus();
+    }
+
+    /// Cursor line and column, both 0-based.
+    pub fn cursor_position(&self) -> (i32, i32) {
+        let iter = self.buffer.iter_at_mark(&self.buffer.get_insert());
+        (iter.line(), iter.line_offset())
+    }
+
+    /// First source line visible in the editor.
+    pub fn top_line(&self) -> i32 {
+        let rect = self.view.visible_rect();
+        self.view.line_at_y(rect.y()).0.line()
+    }
+
+    /// Puts the cursor and editor viewport back where a previous session left
+    /// them. Safe to call before the editor has been laid out.
+    pub fn restore_position(&self, cursor_line: i32, cursor_column: i32, top_line: i32) {
+        if let Some(iter) = self.buffer.iter_at_line_offset(cursor_line, cursor_column) {
+            self.buffer.place_cursor(&iter);
+        }
+        let Some(iter) = self.buffer.iter_at_line(top_line) else {
+            return;
+        };
+        let mark = match self.buffer.mark(RESTORE_MARK) {
+            Some(mark) => {
+                self.buffer.move_mark(&mark, &iter);
+                mark
+            }
+            None => self.buffer.create_mark(Some(RESTORE_MARK), &iter, true),
+        };
+        self.view.scroll_to_mark(&mark, 0.0, true, 0.0, 0.0);
+    }
+
+    /// Opens the editor's find bar, or find and replace with `replace`.
+    pub fn show_editor_search(&self, replace: bool) {
+        if self.viewer_mode.get() {
+            return;
+        }
+        self.set_editor_visible(true);
+        self.editor_search.show(replace);
+    }
+
+    /// Opens the find bar of the pane the user works in: the preview's when
+    /// it has focus or is all that is shown, the editor's otherwise.
+    pub fn show_search(&self) {
+        if self.viewer_mode.get()
+            || !self.editor_visible.get()
+            || self.webview.has_focus()
+            || self.preview_search.has_focus()
+        {
+            self.preview_search.show();
+        } else {
+            self.editor_search.show(false);
+        }
+    }
+
+    pub fn is_viewer_mode(&self) -> bool {
+        self.viewer_mode.get()
+    }
+
+    /// Viewer mode hides the editor, makes the buffer read-only and hands the
+    /// keyboard to the preview. Changes on disk are still picked up, so this
+    /// suits reports regenerated by other tools.
+    pub fn set_viewer_mode(&self, enabled: bool) {
+        self.viewer_mode.set(enabled);
+        self.view.set_editable(!enabled);
+        self.editor_pane.set_visible(!enabled);
+        if enabled {
+            self.webview.grab_focus();
+        } else {
+            self.view.grab_focus();
+        }
+    }
+
+    /// Moves keyboard focus to whichever pane the user works in.
+    pub fn grab_focus(&self) {
+        if self.viewer_mode.get() || !self.editor_visible.get() {
+            self.webview.grab_focus();
+        } else {
+            self.view.grab_focus();
+        }
+    }
+
+    pub fn is_editor_visible(&self) -> bool {
+        self.editor_visible.get()
+    }
+
+    /// Width of the editor pane, or what it will be once the editor is shown.
+    pub fn paned_position(&self) -> i32 {
+        if self.editor_visible.get() {
+            self.paned.position()
+        } else {
+            self.saved_paned_pos.get()
+        }
+    }
+
+    pub fn set_paned_position(&self, position: i32) {
+        self.saved_paned_pos.set(position);
+        if self.editor_visible.get() {
+            self.paned.set_position(position);
+        }
+    }
+
+    pub fn set_editor_visible(&self, visible: bool) {
+        if visible == self.editor_visible.get() {
+            return;
+        }
+        if visible {
+            self.paned.set_position(self.saved_paned_pos.get());
+        } else {
+            let pos = self.paned.position();
+            self.saved_paned_pos
+                .set(if pos > 0 { pos } else { DEFAULT_PANED_POSITION });
+            self.paned.set_position(0);
+        }
+        self.editor_visible.set(visible);
+    }
+
+    /// Follows the editor preferences, now and whenever they change.
+    fn bind_preferences(self: &Rc<Self>) {
+        let prefs = Preferences::default();
+        prefs
+            .bind_property("line-numbers", &self.view, "show-line-numbers")
+            .sync_create()
+            .build();
+        prefs
+            .bind_property("show-outline", &self.outline_revealer, "reveal-child")
+            .sync_create()
+            .build();
+        prefs
+            .bind_property("word-wrap", &self.view, "wrap-mode")
+            .transform_to(|_, wrap: bool| {
+                Some(if wrap {
+                    gtk4::WrapMode::Word
+                } else {
+                    gtk4::WrapMode::None
+                })
+            })
+            .sync_create()
+            .build();
+        self.set_vim_mode(prefs.vim());
+        self.set_style_scheme(&prefs.color_scheme());
+
+        let weak = Rc::downgrade(self);
+        let vim_handler = prefs.connect_vim_notify(move |prefs| {
+            if let Some(doc) = weak.upgrade() {
+                doc.set_vim_mode(prefs.vim());
+            }
+        });
+        let weak = Rc::downgrade(self);
+        let scheme_handler = prefs.connect_color_scheme_notify(move |prefs| {
+            if let Some(doc) = weak.upgrade() {
+                doc.set_style_scheme(&prefs.color_scheme());
+                // Code blocks in the preview follow the editor scheme.
+                doc.render();
+            }
+        });
+        let weak = Rc::downgrade(self);
+        let math_handler = prefs.connect_math_notify(move |_| {
+            if let Some(doc) = weak.upgrade() {
+                // The page only loads MathJax while math is on.
+                doc.page_base_uri.replace(None);
+                doc.render();
+            }
+        });
+        self.prefs_handlers
+            .replace(vec![vim_handler, scheme_handler, math_handler]);
+    }
+
+    fn set_vim_mode(&self, enabled: bool) {
+        let mut vc = self.vim_controller.borrow_mut();
+        if enabled && vc.is_none() {
+            let vim_ctx = VimIMContext::new();
+            vim_ctx.set_client_widget(Some(&self.view));
+            let key_ctrl = EventControllerKey::new();
+            key_ctrl.set_propagation_phase(PropagationPhase::Capture);
+            key_ctrl.set_im_context(Some(&vim_ctx));
+            self.view.add_controller(key_ctrl.clone());
+            *vc = Some(key_ctrl);
+        } else if !enabled && let Some(ctrl) = vc.take() {
+            self.view.remove_controller(&ctrl);
+        }
+    }
+
+    /// Applies editor style scheme `id`, falling back to the default one if
+    /// it is not installed.
+    fn set_style_scheme(&self, id: &str) {
+        let scheme_mgr = sourceview5::StyleSchemeManager::default();
+        if let Some(scheme) = scheme_mgr
+            .scheme(id)
+            .or_else(|| scheme_mgr.scheme(DEFAULT_SCHEME))
+        {
+            self.buffer.set_style_scheme(Some(&scheme));
+        }
+    }
+}
+
+impl Drop for Document {
+    fn drop(&mut self) {
+        let prefs = Preferences::default();
+        for id in self.prefs_handlers.take() {
+            prefs.disconnect(id);
+        }
+    }
+}
+
+/// Reads `file` as UTF-8, returning its contents and entity tag.
+pub fn read_file(file: &gio::File) -> Result<(String, Option<String>), String> {
+    let (bytes, etag) = file
+        .load_contents(None::<&gio::Cancellable>)
+        .map_err(|e| e.to_string())?;
+    let content = String::from_utf8(bytes.to_vec())
+        .map_err(|_| format!("{} is not valid UTF-8 text", file.uri()))?;
+    Ok((content, etag.map(|e| e.to_string())))
+}
@@ -0,0 +1,404 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use gtk4::{gio, glib};
+use gtk4::prelude::*;
+use webkit6::prelude::*;
+use webkit6::{LoadEvent, WebView};
+
+use crate::document::read_file;
+use crate::highlight::{export_scheme, highlight_code_blocks};
+use crate::prefs::{PdfTheme, Preferences, Theme};
+use crate::preview::{
+    base_uri_for_preview, build_html_page, diagram_scripts, document_head, document_title, math_scripts,
+    render_markdown,
+};
+
+/// How long a PDF export may take before it is abandoned.
+const PRINT_TIMEOUT_SECS: u32 = 120;
+
+/// Paper sizes offered for PDF export, as GTK paper names and labels.
+pub const PAPER_SIZES: &[(&str, &str)] = &[
+    ("iso_a4", "A4"),
+    ("na_letter", "US Letter"),
+    ("na_legal", "US Legal"),
+    ("iso_a5", "A5"),
+    ("iso_a3", "A3"),
+];
+
+/// Overrides the light theme with black text on white and no backgrounds.
+const PRINT_THEME_CSS: &str = r#"
+    :root, html, body { background: #fff !important; color: #000 !important; }
+    h1,h2,h3,h4,h5,h6,strong,em { color: #000 !important; }
+    a { color: #000 !important; text-decoration: underline !important; }
+    code, pre, blockquote, th { background: none !important; color: #000 !important; }
+    pre, blockquote { border-color: #999 !important; }
+"#;
+
+/// Layout for pages with a header and footer. Pages are laid out 1.4 times
+/// wider than the printable area so WebKit's shrink-to-fit scales each one to
+/// exactly one sheet; the font is enlarged to match.
+const PAGED_CSS: &str = r#"
+    html, body { padding: 0 !important; margin: 0 !important; }
+    .mv-page { display: flex; flex-direction: column; overflow: hidden; font-size: 140%;
+        page-break-after: always; break-after: page; }
+    .mv-page:last-child { page-break-after: auto; break-after: auto; }
+    .mv-page-overflow, .mv-page-overflow .mv-page-body { aspect-ratio: auto !important; overflow: visible; }
+    .mv-page-header, .mv-page-footer { flex: none; font-size: 0.7em; opacity: 0.7; text-align: center; padding: 0.6em 0; }
+    .mv-page-body { flex: 1; min-height: 0; overflow: hidden; }
+"#;
+
+/// Moves the blocks of `#markview-print-source` onto page-sized boxes with the
+/// title above and "page / pages" below, once images and diagrams are ready.
+const PAGINATE_JS: &str = r#"
+    const loaded = new Promise(resolve => window.addEventListener('load', resolve));
+    window.markviewReady = Promise.all([window.markviewReady, loaded]).then(() => {
+        const source = document.getElementById('markview-print-source');
+        const title = source.dataset.title;
+        const nodes = Array.from(source.childNodes);
+        source.remove();
+        const pages = [];
+        const addPage = () => {
+            const page = document.createElement('div');
+            page.className = 'mv-page';
+            const header = document.createElement('div');
+            header.className = 'mv-page-header';
+            header.textContent = title;
+            const body = document.createElement('div');
+            body.className = 'mv-page-body';
+            const footer = document.createElement('div');
+            footer.className = 'mv-page-footer';
+            page.append(header, body, footer);
+            document.body.appendChild(page);
+            pages.push(page);
+            return body;
+        };
+        let body = addPage();
+        for (const node of nodes) {
+            body.appendChild(node);
+            if (body.scrollHeight <= body.clientHeight) continue;
+            if (body.childNodes.length > 1) {
+                body.removeChild(node);
+                body = addPage();
+                body.appendChild(node);
+            }
+            // A block taller than a page runs on over several sheets, and
+            // only the last of them gets a footer. Numbers count them all.
+            if (body.scrollHeight > body.clientHeight) {
+                const page = body.parentNode;
+                const sheetHeight = page.offsetHeight;
+                page.classList.add('mv-page-overflow');
+                page.dataset.sheets = Math.ceil(page.offsetHeight / sheetHeight - 0.01);
+                body = addPage();
+            }
+        }
+        if (!body.hasChildNodes() && pages.length > 1) pages.pop().remove();
+        const sheets = pages.map(page => Number(page.dataset.sheets || 1));
+        const total = sheets.reduce((sum, count) => sum + count, 0);
+        let sheet = 0;
+        pages.forEach((page, i) => {
+            sheet += sheets[i];
+            page.lastChild.textContent = `${sheet} / ${total}`;
+        });
+    });
+"#;
+
+/// Renders `text` as a standalone HTML page titled and described by its front
+/// matter, or titled after its first heading.
+/// With `inline_images`, local images are embedded as data URIs so the file
+/// can be published on its own; `base_uri` is the directory relative image
+/// paths are resolved against.
+pub fn export_html(text: &str, base_uri: Option<&str>, dark: bool, inline_images: bool) -> String {
+    let prefs = Preferences::default();
+    let scheme = export_scheme(&prefs.color_scheme(), dark);
+    let mut body = highlight_code_blocks(&render_markdown(text, false, prefs.math()), &scheme);
+    if inline_images {
+        body = inline_local_images(&body, base_uri);
+    }
+    body.push_str(&diagram_scripts(&body, dark, false));
+    body.push_str(&math_scripts(&body, false));
+    build_html_page(&body, &document_head(text), dark)
+}
+
+/// Replaces the `src` of every `<img>` that points at a readable local file
+/// with a data URI. Remote and unreadable images are left alone.
+fn inline_local_images(html: &str, base_uri: Option<&str>) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(tag_start) = rest.find("<img") {
+        let tag_end = rest[tag_start..]
+            .find('>')
+            .map_or(rest.len(), |i| tag_start + i);
+        let tag = &rest[tag_start..tag_end];
+        let Some(src_start) = tag.find("src=\"").map(|i| tag_start + i + 5) else {
+            out.push_str(&rest[..tag_end]);
+            rest = &rest[tag_end..];
+            continue;
+        };
+        let Some(src_end) = rest[src_start..tag_end].find('"').map(|i| src_start + i) else {
+            out.push_str(&rest[..tag_end]);
+            rest = &rest[tag_end..];
+            continue;
+        };
+        out.push_str(&rest[..src_start]);
+        let src = &rest[src_start..src_end];
+        match image_data_uri(&src.replace("&amp;", "&"), base_uri) {
+            Some(data_uri) => out.push_str(&data_uri),
+            None => out.push_str(src),
+        }
+        rest = &rest[src_end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn image_data_uri(src: &str, base_uri: Option<&str>) -> Option<String> {
+    let uri = glib::Uri::resolve_relative(base_uri, src, glib::UriFlags::NONE).ok()?;
+    if glib::Uri::peek_scheme(&uri).as_deref() != Some("file") {
+        return None;
+    }
+    let file = gio::File::for_uri(&uri);
+    let (bytes, _) = file.load_contents(None::<&gio::Cancellable>).ok()?;
+    let (content_type, _) = gio::content_type_guess(file.path(), &bytes[..]);
+    let mime = gio::content_type_get_mime_type(&content_type)?;
+    Some(format!("data:{};base64,{}", mime, glib::base64_encode(&bytes)))
+}
+
+/// Page setup for PDF exports from the saved paper, orientation and margins.
+pub fn pdf_page_setup() -> gtk4::PageSetup {
+    let prefs = Preferences::default();
+    let paper = prefs.pdf_paper();
+    let page_setup = gtk4::PageSetup::new();
+    page_setup.set_paper_size(&gtk4::PaperSize::new((!paper.is_empty()).then_some(paper.as_str())));
+    page_setup.set_orientation(if prefs.pdf_landscape() {
+        gtk4::PageOrientation::Landscape
+    } else {
+        gtk4::PageOrientation::Portrait
+    });
+    let margin = prefs.pdf_margin();
+    page_setup.set_top_margin(margin, gtk4::Unit::Mm);
+    page_setup.set_bottom_margin(margin, gtk4::Unit::Mm);
+    page_setup.set_left_margin(margin, gtk4::Unit::Mm);
+    page_setup.set_right_margin(margin, gtk4::Unit::Mm);
+    page_setup
+}
+
+/// Renders `text` as the page printed for a PDF export, in the saved PDF
+/// theme. With headers and footers enabled, the page is split into sheets
+/// sized for `page_setup`, headed by the document title or `name`.
+pub fn pdf_page(text: &str, name: &str, page_setup: &gtk4::PageSetup) -> String {
+    let prefs = Preferences::default();
+    let title = document_title(text);
+    let dark = prefs.pdf_theme() == PdfTheme::Dark;
+    // The Print theme is light too.
+    let scheme = export_scheme(&prefs.color_scheme(), dark);
+    let mut body = highlight_code_blocks(&render_markdown(text, false, prefs.math()), &scheme);
+    let scripts = diagram_scripts(&body, dark, false) + &math_scripts(&body, false);
+    if prefs.pdf_theme() == PdfTheme::Print {
+        body = format!("<style>{PRINT_THEME_CSS}</style>{body}");
+    }
+    if prefs.pdf_header_footer() {
+        let width = page_setup.page_width(gtk4::Unit::Mm);
+        let height = page_setup.page_height(gtk4::Unit::Mm);
+        let layout_width = width * 1.4 * 96.0 / 25.4;
+        body = format!(
+            "<style>{PAGED_CSS} .mv-page {{ width: {layout_width:.0}px; aspect-ratio: {width:.2} / {height:.2}; }}</style>\
+             <div id=\"markview-print-source\" data-title=\"{}\">{body}</div>{scripts}<script>{PAGINATE_JS}</script>",
+            glib::markup_escape_text(title.as_deref().unwrap_or(name)),
+        );
+    } else {
+        body.push_str(&scripts);
+    }
+    build_html_page(&body, &document_head(text), dark)
+}
+
+/// Name of GTK's Print to File printer, the only one that writes the PDF to
+/// the output URI instead of sending it to a print queue.
+fn file_printer() -> Option<String> {
+    let found = Arc::new(Mutex::new(None));
+    gtk4::enumerate_printers(
+        {
+            let found = found.clone();
+            move |printer| {
+                let is_file = printer.is_virtual() && printer.accepts_pdf();
+                if is_file {
+                    *found.lock().unwrap() = Some(printer.name().to_string());
+                }
+                is_file
+            }
+        },
+        true,
+    );
+    found.lock().unwrap().take()
+}
+
+type PrintCallback = Box<dyn FnOnce(Result<(), String>)>;
+
+/// An off-screen web view printing one page to PDF. Holds on to the view
+/// until printing finishes, fails or times out.
+struct PrintJob {
+    webview: RefCell<Option<WebView>>,
+    print_op: RefCell<Option<webkit6::PrintOperation>>,
+    timeout: RefCell<Option<glib::SourceId>>,
+    on_done: RefCell<Option<PrintCallback>>,
+}
+
+impl PrintJob {
+    fn finish(&self, outcome: Result<(), String>) {
+        if let Some(id) = self.timeout.take() {
+            id.remove();
+        }
+        self.print_op.take();
+        self.webview.take();
+        if let Some(on_done) = self.on_done.take() {
+            on_done(outcome);
+        }
+    }
+}
+
+/// Loads `page` into an off-screen web view and prints it to `output` as a
+/// PDF without showing a dialog. `on_done` receives the outcome.
+pub fn print_html(
+    page: &str,
+    base_uri: Option<&str>,
+    output: &gio::File,
+    page_setup: &gtk4::PageSetup,
+    on_done: impl FnOnce(Result<(), String>) + 'static,
+) {
+    let Some(printer) = file_printer() else {
+        on_done(Err("The Print to File printer is not available".to_string()));
+        return;
+    };
+    let webview = WebView::new();
+    let job = Rc::new(PrintJob {
+        webview: RefCell::new(Some(webview.clone())),
+        print_op: RefCell::new(None),
+        timeout: RefCell::new(None),
+        on_done: RefCell::new(Some(Box::new(on_done))),
+    });
+
+    let settings = gtk4::PrintSettings::new();
+    settings.set_printer(&printer);
+    settings.set(gtk4::PRINT_SETTINGS_OUTPUT_URI.as_str(), Some(output.uri().as_str()));
+    settings.set(gtk4::PRINT_SETTINGS_OUTPUT_FILE_FORMAT.as_str(), Some("PDF"));
+    let page_setup = page_setup.clone();
+    let weak = Rc::downgrade(&job);
+    webview.connect_load_changed(move |webview, event| {
+        let Some(job) = weak.upgrade() else {
+            return;
+        };
+        if event != LoadEvent::Finished || job.print_op.borrow().is_some() {
+            return;
+        }
+        let print_op = webkit6::PrintOperation::new(webview);
+        print_op.set_print_settings(&settings);
+        print_op.set_page_setup(&page_setup);
+        print_op.connect_finished({
+            let job = job.clone();
+            move |_| job.finish(Ok(()))
+        });
+        print_op.connect_failed({
+            let job = job.clone();
+            move |_, e| job.finish(Err(format!("Printing failed: {e}")))
+        });
+        job.print_op.replace(Some(print_op));
+        // Diagrams and pagination finish asynchronously after the load.
+        webview.call_async_javascript_function(
+            "await window.markviewReady;",
+            None,
+            None,
+            None,
+            None::<&gio::Cancellable>,
+            move |result| {
+                if let Err(e) = result {
+                    eprintln!("Page did not finish preparing for print: {e}");
+                }
+                // Unless the job timed out in the meantime.
+                let print_op = job.print_op.borrow().clone();
+                if let Some(print_op) = print_op {
+                    print_op.print();
+                }
+            },
+        );
+    });
+    let weak = Rc::downgrade(&job);
+    webview.connect_load_failed(move |_, _, uri, e| {
+        if let Some(job) = weak.upgrade() {
+            job.finish(Err(format!("Could not render {uri}: {e}")));
+        }
+        false
+    });
+    let id = glib::timeout_add_seconds_local_once(PRINT_TIMEOUT_SECS, {
+        let job = job.clone();
+        move || {
+            // The source is gone once this runs.
+            job.timeout.take();
+            job.finish(Err("Timed out waiting for the page to print".to_string()));
+        }
+    });
+    job.timeout.replace(Some(id));
+    webview.load_html(page, base_uri);
+}
+
+/// Converts the Markdown file `input` for `markview --export`. The format
+/// follows the extension of `output`: `.pdf`, or `.html`/`.htm`. PDFs use the
+/// page setup and theme last chosen in the export dialog. Runs its own main
+/// loop for PDFs, so it must be called before the application starts, and
+/// fails without a display.
+pub fn export_file(input: &gio::File, output: &gio::File) -> Result<(), String> {
+    let extension = output
+        .basename()
+        .and_then(|name| name.extension().map(|e| e.to_string_lossy().to_lowercase()));
+    let (text, _) = read_file(input)?;
+    let base_uri = base_uri_for_preview(Some(input));
+    match extension.as_deref() {
+        Some("html" | "htm") => {
+            // Code blocks are highlighted through GTK when a display is
+            // available and left plain otherwise.
+            let _ = gtk4::init();
+            let dark = Preferences::default().theme() == Theme::ForceDark;
+            let html = export_html(&text, base_uri.as_deref(), dark, true);
+            output
+                .replace_contents(
+                    html.as_bytes(),
+                    None,
+                    false,
+                    gio::FileCreateFlags::REPLACE_DESTINATION,
+                    None::<&gio::Cancellable>,
+                )
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        }
+        Some("pdf") => {
+            // WebKit lays the page out for printing, which needs a display.
+            gtk4::init().map_err(|e| {
+                format!("PDF export needs a graphical session (no display available: {e}); HTML export does not")
+            })?;
+            let name = input
+                .basename()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let page_setup = pdf_page_setup();
+            let page = pdf_page(&text, &name, &page_setup);
+            let main_loop = glib::MainLoop::new(None, false);
+            let result = Rc::new(RefCell::new(None));
+            print_html(&page, base_uri.as_deref(), output, &page_setup, {
+                let main_loop = main_loop.clone();
+                let result = result.clone();
+                move |outcome| {
+                    result.replace(Some(outcome));
+                    main_loop.quit();
+                }
+            });
+            main_loop.run();
+            result
+                .take()
+                .unwrap_or_else(|| Err("Printing did not finish".to_string()))
+        }
+        _ => Err(format!(
+            "Cannot tell the export format of {}; use a .pdf or .html file name",
+            output.uri()
+        )),
+    }
+}
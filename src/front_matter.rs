@@ -0,0 +1,527 @@
+use std::ops::Range;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Between `---` lines.
+    Yaml,
+    /// Between `+++` lines.
+    Toml,
+}
+
+#[derive(Clone, PartialEq)]
+pub enum Value {
+    Text(String),
+    List(Vec<String>),
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Field {
+    pub key: String,
+    pub value: Value,
+    /// Byte range of the field's lines in the document, final newline
+    /// included.
+    range: Range<usize>,
+}
+
+/// Metadata block at the very top of a document. Only top-level text and
+/// list values are read; nested maps and tables are only named, and left
+/// alone.
+#[derive(Clone, PartialEq)]
+pub struct FrontMatter {
+    pub format: Format,
+    pub fields: Vec<Field>,
+    /// Keys of nested YAML maps and names of TOML tables.
+    nested: Vec<String>,
+    /// Byte range of the lines between the delimiters.
+    body: Range<usize>,
+    /// Where new top-level fields go: the end of the block, or before the
+    /// first TOML table.
+    insert_at: usize,
+}
+
+impl FrontMatter {
+    /// Field named `key`, compared case-insensitively.
+    pub fn field(&self, key: &str) -> Option<&Field> {
+        self.fields
+            .iter()
+            .find(|field| field.key.eq_ignore_ascii_case(key))
+    }
+
+    /// Value of `key` as text, with lists joined by commas. `None` when
+    /// missing or empty.
+    pub fn text(&self, key: &str) -> Option<String> {
+        let text = match &self.field(key)?.value {
+            Value::Text(text) => text.clone(),
+            Value::List(items) => items.join(", "),
+        };
+        (!text.is_empty()).then_some(text)
+    }
+
+    /// Items of `key`, or a single item for a text value.
+    pub fn list(&self, key: &str) -> Vec<String> {
+        match self.field(key).map(|field| &field.value) {
+            Some(Value::List(items)) => items.clone(),
+            Some(Value::Text(text)) if !text.is_empty() => vec![text.clone()],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether `key` holds a nested map or table, compared
+    /// case-insensitively.
+    pub fn is_nested(&self, key: &str) -> bool {
+        self.nested.iter().any(|nested| nested.eq_ignore_ascii_case(key))
+    }
+
+    pub fn title(&self) -> Option<String> {
+        self.text("title")
+    }
+}
+
+/// Lines of `text` without their line breaks, with the byte offset each
+/// starts at and the offset just past its line break.
+fn lines(text: &str, base: usize) -> impl Iterator<Item = (usize, &str, usize)> {
+    let mut start = 0;
+    std::iter::from_fn(move || {
+        if start >= text.len() {
+            return None;
+        }
+        let line_start = start;
+        start = text[start..].find('\n').map_or(text.len(), |i| start + i + 1);
+        let line = text[line_start..start].trim_end_matches('\n').trim_end_matches('\r');
+        Some((base + line_start, line, base + start))
+    })
+}
+
+/// Parses the front matter at the top of `text`, if there is one. Only the
+/// block itself is read, so this is cheap on long documents.
+pub fn parse(text: &str) -> Option<FrontMatter> {
+    let (format, delimiter) = if text.starts_with("---") {
+        (Format::Yaml, "---")
+    } else if text.starts_with("+++") {
+        (Format::Toml, "+++")
+    } else {
+        return None;
+    };
+    let mut all = lines(text, 0);
+    let (_, first, body_start) = all.next()?;
+    if first.trim_end() != delimiter {
+        return None;
+    }
+    let (body_end, _, _) = all.find(|(_, line, _)| {
+        let line = line.trim_end();
+        line == delimiter || (format == Format::Yaml && line == "...")
+    })?;
+    let body = &text[body_start..body_end];
+    let (fields, insert_at, nested) = match format {
+        Format::Yaml => {
+            let (fields, nested) = parse_yaml(body, body_start);
+            (fields, body_end, nested)
+        }
+        Format::Toml => parse_toml(body, body_start),
+    };
+    Some(FrontMatter {
+        format,
+        fields,
+        nested,
+        body: body_start..body_end,
+        insert_at,
+    })
+}
+
+/// Also returns the keys of nested maps.
+fn parse_yaml(body: &str, base: usize) -> (Vec<Field>, Vec<String>) {
+    let lines: Vec<_> = lines(body, base).collect();
+    let mut fields = Vec::new();
+    let mut nested = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let (start, line, mut end) = lines[i];
+        i += 1;
+        if line.starts_with([' ', '\t', '#', '-']) || line.trim().is_empty() {
+            continue;
+        }
+        let Some((key, rest)) = line.split_once(':') else {
+            continue;
+        };
+        // Indented lines and list items belong to this key.
+        let mut continuation = Vec::new();
+        while i < lines.len() {
+            let (_, next, next_end) = lines[i];
+            if !(next.starts_with([' ', '\t']) || next.starts_with("- ") || next.trim().is_empty()) {
+                break;
+            }
+            if !next.trim().is_empty() {
+                end = next_end;
+                continuation.push(next.trim());
+            }
+            i += 1;
+        }
+        let rest = rest.trim();
+        let value = if rest.starts_with('[') && rest.ends_with(']') {
+            Value::List(split_list(&rest[1..rest.len() - 1]))
+        } else if rest.starts_with('|') || rest.starts_with('>') {
+            let separator = if rest.starts_with('|') { "\n" } else { " " };
+            Value::Text(continuation.join(separator))
+        } else if !rest.is_empty() {
+            Value::Text(unquote(strip_comment(rest)))
+        } else if !continuation.is_empty() && continuation.iter().all(|l| l.starts_with('-')) {
+            Value::List(
+                continuation
+                    .iter()
+                    .map(|item| unquote(strip_comment(item[1..].trim())))
+                    .collect(),
+            )
+        } else {
+            nested.push(unquote(key.trim()));
+            continue;
+        };
+        fields.push(Field {
+            key: unquote(key.trim()),
+            value,
+            range: start..end,
+        });
+    }
+    (fields, nested)
+}
+
+/// Also returns the offset of the first table header, or the end of `body`,
+/// and the names of tables, including those made by dotted keys.
+fn parse_toml(body: &str, base: usize) -> (Vec<Field>, usize, Vec<String>) {
+    let lines: Vec<_> = lines(body, base).collect();
+    let mut fields = Vec::new();
+    let mut nested = Vec::new();
+    let mut first_table = None;
+    let mut i = 0;
+    while i < lines.len() {
+        let (start, line, mut end) = lines[i];
+        i += 1;
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            first_table.get_or_insert(start);
+            let name = trimmed.trim_matches(['[', ']']).split('.').next().unwrap_or_default();
+            nested.push(unquote(name.trim()));
+            continue;
+        }
+        // Keys after the first table header belong to that table.
+        if first_table.is_some() || trimmed.starts_with('#') {
+            continue;
+        }
+        let Some((key, rest)) = trimmed.split_once('=') else {
+            continue;
+        };
+        let key = key.trim();
+        if !key.starts_with(['"', '\'']) && let Some((table, _)) = key.split_once('.') {
+            nested.push(table.trim().to_string());
+            continue;
+        }
+        let mut rest = strip_comment(rest.trim()).to_string();
+        // Arrays may span several lines.
+        if rest.starts_with('[') {
+            while !rest.ends_with(']') && i < lines.len() {
+                let (_, next, next_end) = lines[i];
+                rest.push(' ');
+                rest.push_str(strip_comment(next.trim()));
+                end = next_end;
+                i += 1;
+            }
+        }
+        let value = if rest.starts_with('[') && rest.ends_with(']') {
+            Value::List(split_list(&rest[1..rest.len() - 1]))
+        } else {
+            Value::Text(unquote(&rest))
+        };
+        fields.push(Field {
+            key: unquote(key),
+            value,
+            range: start..end,
+        });
+    }
+    (fields, first_table.unwrap_or(base + body.len()), nested)
+}
+
+/// Drops a trailing `# comment` outside quotes.
+fn strip_comment(value: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in value.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '#') if i == 0 || value[..i].ends_with(char::is_whitespace) => {
+                return value[..i].trim_end();
+            }
+            _ => {}
+        }
+    }
+    value
+}
+
+/// Splits the inside of a `[a, "b", 'c']` list on commas outside quotes.
+fn split_list(inner: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut quote = None;
+    let mut item_start = 0;
+    for (i, c) in inner.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, ',') => {
+                items.push(&inner[item_start..i]);
+                item_start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&inner[item_start..]);
+    items
+        .into_iter()
+        .map(|item| unquote(item.trim()))
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn unquote(value: &str) -> String {
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        let mut out = String::new();
+        let mut chars = value[1..value.len() - 1].chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('r') => out.push('\r'),
+                Some(c) => out.push(c),
+                None => out.push('\\'),
+            }
+        }
+        out
+    } else if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+        value[1..value.len() - 1].replace("''", "'")
+    } else {
+        value.to_string()
+    }
+}
+
+/// A double-quoted string, which YAML and TOML escape alike.
+fn quote(value: &str) -> String {
+    let mut out = String::from('"');
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// One `key: value` (YAML) or `key = value` (TOML) line.
+fn field_line(format: Format, key: &str, value: &Value) -> String {
+    let value = match value {
+        Value::Text(text) => match format {
+            // Plain YAML scalars read back as they are, unless they contain
+            // something YAML gives a meaning to.
+            Format::Yaml
+                if !text.contains([':', '#', '"', '\'', '[', ']', '{', '}', ',', '&', '*', '!', '|', '>', '%', '@', '`', '\n', '\t', '\r'])
+                    && text.trim() == text
+                    && !text.starts_with(['-', '?']) =>
+            {
+                text.clone()
+            }
+            _ => quote(text),
+        },
+        Value::List(items) => {
+            let items: Vec<String> = items.iter().map(|item| quote(item)).collect();
+            format!("[{}]", items.join(", "))
+        }
+    };
+    match format {
+        Format::Yaml => format!("{key}: {value}\n"),
+        Format::Toml => format!("{key} = {value}\n"),
+    }
+}
+
+fn is_empty(value: &Value) -> bool {
+    match value {
+        Value::Text(text) => text.is_empty(),
+        Value::List(items) => items.is_empty(),
+    }
+}
+
+/// Sets the front matter fields in `changes`, removing those with empty
+/// values, and leaves every other line as it was, including fields whose
+/// value is unchanged. Nested maps and tables are never changed, nor shadowed
+/// by a new field of the same name. Returns the byte range of
+/// `text` to replace and its replacement; without front matter, a YAML
+/// block is inserted at the top.
+pub fn update(text: &str, changes: &[(String, Value)]) -> (Range<usize>, String) {
+    let Some(front_matter) = parse(text) else {
+        let fields: String = changes
+            .iter()
+            .filter(|(_, value)| !is_empty(value))
+            .map(|(key, value)| field_line(Format::Yaml, key, value))
+            .collect();
+        if fields.is_empty() {
+            return (0..0, String::new());
+        }
+        return (0..0, format!("---\n{fields}---\n\n"));
+    };
+    let format = front_matter.format;
+    let mut out = String::new();
+    let mut pos = front_matter.body.start;
+    for field in &front_matter.fields {
+        let Some((key, value)) = changes
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(&field.key))
+            .filter(|(_, value)| *value != field.value)
+        else {
+            continue;
+        };
+        out.push_str(&text[pos..field.range.start]);
+        if !is_empty(value) {
+            out.push_str(&field_line(format, key, value));
+        }
+        pos = field.range.end;
+    }
+    out.push_str(&text[pos..front_matter.insert_at]);
+    for (key, value) in changes {
+        if front_matter.field(key).is_none() && !front_matter.is_nested(key) && !is_empty(value) {
+            if !out.is_empty() && !out.ends_with('\n') {
+                out.push('\n');
+            }
+            out.push_str(&field_line(format, key, value));
+        }
+    }
+    out.push_str(&text[front_matter.insert_at..front_matter.body.end]);
+    (front_matter.body, out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(value: &str) -> Value {
+        Value::Text(value.to_string())
+    }
+
+    fn list(items: &[&str]) -> Value {
+        Value::List(items.iter().map(|item| item.to_string()).collect())
+    }
+
+    /// `text` with `changes` applied.
+    fn apply(text: &str, changes: &[(&str, Value)]) -> String {
+        let changes: Vec<(String, Value)> = changes
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect();
+        let (range, replacement) = update(text, &changes);
+        format!("{}{}{}", &text[..range.start], replacement, &text[range.end..])
+    }
+
+    #[test]
+    fn parses_yaml() {
+        let front_matter = parse(
+            "---\ntitle: \"Hello: World\" # comment\ntags:\n  - a\n  - 'b c'\n\
+             nested:\n  x: 1\nkeywords: [x, \"y, z\"]\ndescription: |\n  one\n  two\n---\n# Body\n",
+        )
+        .unwrap();
+        assert!(front_matter.format == Format::Yaml);
+        assert_eq!(front_matter.title().as_deref(), Some("Hello: World"));
+        assert_eq!(front_matter.list("tags"), ["a", "b c"]);
+        assert_eq!(front_matter.list("keywords"), ["x", "y, z"]);
+        assert_eq!(front_matter.text("description").as_deref(), Some("one\ntwo"));
+        assert!(front_matter.field("nested").is_none());
+    }
+
+    #[test]
+    fn parses_toml_until_first_table() {
+        let front_matter =
+            parse("+++\ntitle = \"T\" # comment\ntags = [\n  \"a\",\n  \"b\"\n]\n[extra]\nauthor = \"X\"\n+++\nbody").unwrap();
+        assert!(front_matter.format == Format::Toml);
+        assert_eq!(front_matter.title().as_deref(), Some("T"));
+        assert_eq!(front_matter.list("tags"), ["a", "b"]);
+        assert!(front_matter.field("author").is_none());
+        assert!(front_matter.is_nested("extra"));
+    }
+
+    #[test]
+    fn leaves_nested_keys_alone() {
+        let source = "---\ntitle: T\nauthor:\n  name: A\n  email: a@example.com\n---\nbody\n";
+        let front_matter = parse(source).unwrap();
+        assert!(front_matter.is_nested("Author"));
+        assert!(front_matter.field("author").is_none());
+        assert_eq!(apply(source, &[("author", text("B"))]), source);
+        assert_eq!(
+            apply(source, &[("author", text("B")), ("title", text("U"))]),
+            source.replace("title: T", "title: U")
+        );
+
+        let source = "+++\ntitle = \"T\"\nauthor.name = \"A\"\n[links]\nhome = \"x\"\n+++\n";
+        let front_matter = parse(source).unwrap();
+        assert!(front_matter.is_nested("author") && front_matter.is_nested("links"));
+        assert_eq!(apply(source, &[("author", text("B")), ("links", text("y"))]), source);
+    }
+
+    #[test]
+    fn rejects_unterminated_blocks() {
+        assert!(parse("---\ntitle: x\n").is_none());
+        assert!(parse("----\ntitle: x\n---\n").is_none());
+        assert!(parse("# Title\n").is_none());
+    }
+
+    #[test]
+    fn yaml_update_keeps_untouched_fields() {
+        let source = "---\ntitle: Old\ndescription: |\n  one\n  two\ndate: 2024-01-01\n---\nbody\n";
+        let front_matter = parse(source).unwrap();
+        let updated = apply(
+            source,
+            &[
+                ("title", text("New")),
+                ("description", front_matter.field("description").unwrap().value.clone()),
+                ("date", text("")),
+                ("tags", list(&["a", "b"])),
+            ],
+        );
+        assert_eq!(
+            updated,
+            "---\ntitle: New\ndescription: |\n  one\n  two\ntags: [\"a\", \"b\"]\n---\nbody\n"
+        );
+    }
+
+    #[test]
+    fn yaml_round_trips_special_text() {
+        for value in ["one\ntwo", "a: b", "\"quoted\" \\ back", "- dash", " padded "] {
+            let updated = apply("---\ntitle: x\n---\n", &[("title", text(value))]);
+            assert_eq!(parse(&updated).unwrap().title().as_deref(), Some(value), "{updated}");
+        }
+    }
+
+    #[test]
+    fn toml_update_keeps_native_values() {
+        let source = "+++\ntitle = \"Old\"\ndate = 2024-01-01\n[extra]\nx = 1\n+++\n";
+        let updated = apply(
+            source,
+            &[("title", text("two\nlines")), ("date", text("2024-01-01")), ("author", text("Ann"))],
+        );
+        assert_eq!(
+            updated,
+            "+++\ntitle = \"two\\nlines\"\ndate = 2024-01-01\nauthor = \"Ann\"\n[extra]\nx = 1\n+++\n"
+        );
+        assert_eq!(parse(&updated).unwrap().title().as_deref(), Some("two\nlines"));
+    }
+
+    #[test]
+    fn adds_yaml_block_when_missing() {
+        assert_eq!(
+            apply("# Hi\n", &[("title", text("Hi")), ("author", text(""))]),
+            "---\ntitle: Hi\n---\n\n# Hi\n"
+        );
+        assert_eq!(apply("# Hi\n", &[("title", text(""))]), "# Hi\n");
+    }
+}
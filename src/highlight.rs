@@ -0,0 +1,276 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+use gtk4::glib;
+use gtk4::prelude::*;
+use sourceview5::prelude::*;
+use sourceview5::{LanguageManager, StyleScheme, StyleSchemeManager};
+
+use crate::prefs::DEFAULT_SCHEME;
+
+const CODE_START: &str = "<pre><code class=\"language-";
+const CODE_END: &str = "</code></pre>";
+
+/// Highlighted blocks kept between renders, so only edited blocks are
+/// highlighted again while typing. Blocks of the current render are kept
+/// even past the limit.
+const CACHE_LIMIT: usize = 256;
+
+/// Style scheme id, language and source of a code block.
+type BlockKey = (String, String, String);
+
+thread_local! {
+    /// Highlighted `<pre>` elements, with the render that last used each.
+    static CACHE: RefCell<HashMap<BlockKey, (String, u64)>> = RefCell::new(HashMap::new());
+    /// Counts calls to `highlight_code_blocks`.
+    static RENDER: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Colors the fenced code blocks of rendered Markdown with the GtkSourceView
+/// language for their info string and the style scheme `scheme_id`,
+/// as inline styles so exports keep them. Blocks in unknown languages, and
+/// everything when GTK is not initialized, are left as they are.
+pub fn highlight_code_blocks(html: &str, scheme_id: &str) -> String {
+    rewrite_code_blocks(html, scheme_id, |key, language, scheme, render| {
+        cached(&key, render).or_else(|| {
+            let block = highlight(&key.2, &key.1, &language, scheme);
+            store(key, block.clone(), render);
+            Some(block)
+        })
+    })
+}
+
+/// The style scheme for code blocks on a light or `dark` exported page: the
+/// editor scheme `scheme_id` when its background suits the page, Adwaita in
+/// the matching variant otherwise.
+pub fn export_scheme(scheme_id: &str, dark: bool) -> String {
+    let scheme_dark = gtk4::is_initialized_main_thread()
+        && StyleSchemeManager::default()
+            .scheme(scheme_id)
+            .and_then(|scheme| scheme.style("text"))
+            .and_then(|style| {
+                let tag = gtk4::TextTag::new(None);
+                style.apply(&tag);
+                tag.is_background_set().then(|| tag.background_rgba()).flatten()
+            })
+            .is_some_and(|bg| 0.2126 * bg.red() + 0.7152 * bg.green() + 0.0722 * bg.blue() < 0.5);
+    if scheme_dark == dark {
+        scheme_id.to_string()
+    } else if dark {
+        "Adwaita-dark".to_string()
+    } else {
+        "Adwaita".to_string()
+    }
+}
+
+/// A code block that `highlight_cached_code_blocks` found no cached colors
+/// for.
+pub struct PendingBlock {
+    key: BlockKey,
+    language: sourceview5::Language,
+    scheme: StyleScheme,
+}
+
+/// Like `highlight_code_blocks`, but only takes blocks from the cache so it
+/// returns quickly while typing. The others are left plain and returned, for
+/// `highlight_pending` to fill the cache with.
+pub fn highlight_cached_code_blocks(html: &str, scheme_id: &str) -> (String, Vec<PendingBlock>) {
+    let mut pending = Vec::new();
+    let html = rewrite_code_blocks(html, scheme_id, |key, language, scheme, render| {
+        let block = cached(&key, render);
+        if block.is_none() {
+            pending.push(PendingBlock {
+                key,
+                language,
+                scheme: scheme.clone(),
+            });
+        }
+        block
+    });
+    (html, pending)
+}
+
+/// Highlights one block of `pending` into the cache. Returns whether any are
+/// left.
+pub fn highlight_pending(pending: &mut Vec<PendingBlock>) -> bool {
+    if let Some(block) = pending.pop() {
+        let html = highlight(&block.key.2, &block.key.1, &block.language, &block.scheme);
+        let render = RENDER.with(Cell::get);
+        store(block.key, html, render);
+    }
+    !pending.is_empty()
+}
+
+/// Replaces each code block in a known language with what `color` returns
+/// for it, if anything.
+fn rewrite_code_blocks(
+    html: &str,
+    scheme_id: &str,
+    mut color: impl FnMut(BlockKey, sourceview5::Language, &StyleScheme, u64) -> Option<String>,
+) -> String {
+    if !gtk4::is_initialized_main_thread() || !html.contains(CODE_START) {
+        return html.to_string();
+    }
+    let scheme_mgr = StyleSchemeManager::default();
+    let Some(scheme) = scheme_mgr
+        .scheme(scheme_id)
+        .or_else(|| scheme_mgr.scheme(DEFAULT_SCHEME))
+    else {
+        return html.to_string();
+    };
+
+    let render = RENDER.with(|render| {
+        render.set(render.get() + 1);
+        render.get()
+    });
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find(CODE_START) {
+        let lang_start = start + CODE_START.len();
+        let Some(lang_end) = rest[lang_start..].find("\">").map(|i| lang_start + i) else {
+            break;
+        };
+        let code_start = lang_end + 2;
+        let Some(code_end) = rest[code_start..].find(CODE_END).map(|i| code_start + i) else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        let lang = &rest[lang_start..lang_end];
+        let block = find_language(&unescape_html(lang)).and_then(|language| {
+            let key = (scheme.id().to_string(), lang.to_string(), unescape_html(&rest[code_start..code_end]));
+            color(key, language, &scheme, render)
+        });
+        match block {
+            Some(block) => out.push_str(&block),
+            None => out.push_str(&rest[start..code_end + CODE_END.len()]),
+        }
+        rest = &rest[code_end + CODE_END.len()..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn cached(key: &BlockKey, render: u64) -> Option<String> {
+    CACHE.with(|cache| {
+        cache.borrow_mut().get_mut(key).map(|(block, used)| {
+            *used = render;
+            block.clone()
+        })
+    })
+}
+
+fn store(key: BlockKey, block: String, render: u64) {
+    CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if cache.len() >= CACHE_LIMIT {
+            cache.retain(|_, (_, used)| *used == render);
+        }
+        cache.insert(key, (block, render));
+    });
+}
+
+/// Looks `lang` up as a GtkSourceView language id, then as a file extension
+/// (`py`, `sh`, `ts`, …).
+fn find_language(lang: &str) -> Option<sourceview5::Language> {
+    let manager = LanguageManager::default();
+    let lang = lang.to_lowercase();
+    manager
+        .language(&lang)
+        .or_else(|| manager.guess_language(Some(format!("code.{lang}")), None))
+}
+
+/// `lang` is the info string as escaped in the rendered HTML.
+fn highlight(code: &str, lang: &str, language: &sourceview5::Language, scheme: &StyleScheme) -> String {
+    let buffer = sourceview5::Buffer::new(None);
+    buffer.set_language(Some(language));
+    buffer.set_highlight_matching_brackets(false);
+    buffer.set_style_scheme(Some(scheme));
+    buffer.set_text(code);
+    let (start, end) = buffer.bounds();
+    buffer.ensure_highlight(&start, &end);
+
+    let pre_style = scheme
+        .style("text")
+        .map(|style| {
+            let tag = gtk4::TextTag::new(None);
+            style.apply(&tag);
+            css_for_tags(&[tag])
+        })
+        .unwrap_or_default();
+    let mut out = format!("<pre style=\"{pre_style}\"><code class=\"language-{lang}\">");
+    let mut iter = start;
+    while !iter.is_end() {
+        let mut next = iter;
+        next.forward_to_tag_toggle(None::<&gtk4::TextTag>);
+        let text = glib::markup_escape_text(&iter.text(&next));
+        let style = css_for_tags(&iter.tags());
+        if style.is_empty() {
+            out.push_str(&text);
+        } else {
+            out.push_str(&format!("<span style=\"{style}\">{text}</span>"));
+        }
+        iter = next;
+    }
+    out.push_str(CODE_END);
+    out
+}
+
+/// Inline CSS for text carrying `tags`, which are in ascending priority so
+/// later tags win.
+fn css_for_tags(tags: &[gtk4::TextTag]) -> String {
+    let mut color = None;
+    let mut background = None;
+    let mut bold = None;
+    let mut italic = None;
+    let mut underline = None;
+    let mut strikethrough = None;
+    for tag in tags {
+        if tag.is_foreground_set() {
+            color = tag.foreground_rgba();
+        }
+        if tag.is_background_set() {
+            background = tag.background_rgba();
+        }
+        if tag.is_weight_set() {
+            bold = Some(tag.weight() >= 600);
+        }
+        if tag.is_style_set() {
+            italic = Some(tag.style() != gtk4::pango::Style::Normal);
+        }
+        if tag.is_underline_set() {
+            underline = Some(tag.underline() != gtk4::pango::Underline::None);
+        }
+        if tag.is_strikethrough_set() {
+            strikethrough = Some(tag.is_strikethrough());
+        }
+    }
+    let mut css = String::new();
+    if let Some(color) = color {
+        css.push_str(&format!("color:{color};"));
+    }
+    if let Some(background) = background {
+        css.push_str(&format!("background-color:{background};"));
+    }
+    if bold == Some(true) {
+        css.push_str("font-weight:bold;");
+    }
+    if italic == Some(true) {
+        css.push_str("font-style:italic;");
+    }
+    match (underline == Some(true), strikethrough == Some(true)) {
+        (true, true) => css.push_str("text-decoration:underline line-through;"),
+        (true, false) => css.push_str("text-decoration:underline;"),
+        (false, true) => css.push_str("text-decoration:line-through;"),
+        (false, false) => {}
+    }
+    css
+}
+
+/// Reverses the escaping pulldown-cmark applies to code block text.
+fn unescape_html(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
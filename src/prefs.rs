@@ -0,0 +1,281 @@
+use std::path::PathBuf;
+
+use adw::ColorScheme;
+use gtk4::glib;
+use gtk4::prelude::*;
+use gtk4::subclass::prelude::*;
+
+pub const DEFAULT_SCHEME: &str = "Adwaita-dark";
+const GROUP: &str = "Preferences";
+
+pub const EDITOR_SCHEMES: &[&str] = &[
+    "Adwaita-dark",
+    "Adwaita",
+    "Kate",
+    "Kate-dark",
+    "Cobalt",
+    "Solarized-dark",
+    "Solarized-light",
+    "Monokai",
+    "Builder",
+    "Oblivion",
+];
+
+fn config_path() -> PathBuf {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config"))
+        })
+        .unwrap_or_else(|| PathBuf::from("."));
+    config.join("MarkView").join("preferences.ini")
+}
+
+/// Directory for data that should survive restarts but is not configuration,
+/// such as the last session and crash-recovery snapshots.
+pub fn state_dir() -> PathBuf {
+    let state = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".local").join("state"))
+        })
+        .unwrap_or_else(|| PathBuf::from("."));
+    state.join("MarkView")
+}
+
+/// Application appearance, independent of the editor color scheme.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, glib::Enum)]
+#[enum_type(name = "MarkViewTheme")]
+pub enum Theme {
+    #[default]
+    Default,
+    ForceDark,
+    ForceLight,
+}
+
+impl From<Theme> for ColorScheme {
+    fn from(theme: Theme) -> Self {
+        match theme {
+            Theme::Default => ColorScheme::Default,
+            Theme::ForceDark => ColorScheme::ForceDark,
+            Theme::ForceLight => ColorScheme::ForceLight,
+        }
+    }
+}
+
+/// Stylesheet used for PDF exports.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, glib::Enum)]
+#[enum_type(name = "MarkViewPdfTheme")]
+pub enum PdfTheme {
+    #[default]
+    Light,
+    Dark,
+    /// Black on white without backgrounds, to save ink.
+    Print,
+}
+
+mod imp {
+    use std::cell::{Cell, RefCell};
+
+    use super::*;
+
+    #[derive(glib::Properties)]
+    #[properties(wrapper_type = super::Preferences)]
+    pub struct Preferences {
+        #[property(get, set, builder(Theme::Default))]
+        theme: Cell<Theme>,
+        /// Style scheme id for the editor.
+        #[property(get, set)]
+        color_scheme: RefCell<String>,
+        #[property(get, set)]
+        vim: Cell<bool>,
+        #[property(get, set, default = true)]
+        line_numbers: Cell<bool>,
+        #[property(get, set, default = true)]
+        word_wrap: Cell<bool>,
+        #[property(get, set, default = true)]
+        sync_scroll: Cell<bool>,
+        /// Show the outline panel next to the editor.
+        #[property(get, set)]
+        show_outline: Cell<bool>,
+        /// Typeset TeX between dollar signs.
+        #[property(get, set, default = true)]
+        math: Cell<bool>,
+        /// Keep the previous version of a file as `<name>~` when saving.
+        #[property(get, set)]
+        backup: Cell<bool>,
+        /// Paper size name for PDF export; empty for the locale's default.
+        #[property(get, set)]
+        pdf_paper: RefCell<String>,
+        #[property(get, set)]
+        pdf_landscape: Cell<bool>,
+        /// Page margins for PDF export, in millimetres.
+        #[property(get, set, minimum = 0.0, maximum = 50.0, default = 15.0)]
+        pdf_margin: Cell<f64>,
+        #[property(get, set, builder(PdfTheme::Light))]
+        pdf_theme: Cell<PdfTheme>,
+        /// Print the title above and page numbers below every page.
+        #[property(get, set)]
+        pdf_header_footer: Cell<bool>,
+    }
+
+    impl Default for Preferences {
+        fn default() -> Self {
+            Self {
+                theme: Cell::new(Theme::Default),
+                color_scheme: RefCell::new(DEFAULT_SCHEME.to_string()),
+                vim: Cell::new(false),
+                line_numbers: Cell::new(true),
+                word_wrap: Cell::new(true),
+                sync_scroll: Cell::new(true),
+                show_outline: Cell::new(false),
+                math: Cell::new(true),
+                backup: Cell::new(false),
+                pdf_paper: RefCell::new(String::new()),
+                pdf_landscape: Cell::new(false),
+                pdf_margin: Cell::new(15.0),
+                pdf_theme: Cell::new(PdfTheme::Light),
+                pdf_header_footer: Cell::new(false),
+            }
+        }
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for Preferences {
+        const NAME: &'static str = "MarkViewPreferences";
+        type Type = super::Preferences;
+    }
+
+    #[glib::derived_properties]
+    impl ObjectImpl for Preferences {}
+}
+
+glib::wrapper! {
+    /// Every user preference as a typed property. Widgets bind to the
+    /// properties directly; each change is written to `preferences.ini`.
+    pub struct Preferences(ObjectSubclass<imp::Preferences>);
+}
+
+impl Default for Preferences {
+    /// The shared instance, loaded from disk on first use.
+    fn default() -> Self {
+        thread_local! {
+            static INSTANCE: Preferences = Preferences::load();
+        }
+        INSTANCE.with(|prefs| prefs.clone())
+    }
+}
+
+impl Preferences {
+    fn load() -> Self {
+        let prefs: Self = glib::Object::new();
+        let key_file = glib::KeyFile::new();
+        match std::fs::read_to_string(config_path()) {
+            Ok(content) => {
+                // Files written by older versions have no group header.
+                let content = if content.trim_start().starts_with('[') {
+                    content
+                } else {
+                    format!("[{GROUP}]\n{content}")
+                };
+                match key_file.load_from_data(&content, glib::KeyFileFlags::NONE) {
+                    Ok(()) => prefs.read_from(&key_file),
+                    Err(e) => eprintln!("Ignoring unreadable preferences: {e}"),
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("Failed to read preferences: {e}"),
+        }
+        prefs.connect_notify_local(None, |prefs, _| prefs.save());
+        prefs
+    }
+
+    /// Sets every property the file has a valid value for. Out-of-range
+    /// numbers, e.g. from editing the file by hand, are skipped: glib panics
+    /// on them.
+    fn read_from(&self, key_file: &glib::KeyFile) {
+        for pspec in self.list_properties().iter() {
+            let name = pspec.name();
+            let type_ = pspec.value_type();
+            let value = if type_ == bool::static_type() {
+                key_file.boolean(GROUP, name).ok().map(|v| v.to_value())
+            } else if type_ == f64::static_type() {
+                key_file.double(GROUP, name).ok().and_then(|v| {
+                    let spec = pspec.downcast_ref::<glib::ParamSpecDouble>()?;
+                    if !(spec.minimum()..=spec.maximum()).contains(&v) {
+                        eprintln!("Ignoring out-of-range preference {name}={v}");
+                        return None;
+                    }
+                    Some(v.to_value())
+                })
+            } else if type_ == String::static_type() {
+                key_file.string(GROUP, name).ok().map(|v| v.to_value())
+            } else if let Some(class) = glib::EnumClass::with_type(type_) {
+                key_file
+                    .string(GROUP, name)
+                    .ok()
+                    .and_then(|nick| class.to_value_by_nick(&nick))
+            } else {
+                None
+            };
+            if let Some(value) = value {
+                self.set_property_from_value(name, &value);
+            }
+        }
+    }
+
+    /// Writes every preference, in declaration order, replacing the file
+    /// atomically.
+    fn save(&self) {
+        let key_file = glib::KeyFile::new();
+        for pspec in self.list_properties().iter() {
+            let name = pspec.name();
+            let value = self.property_value(name);
+            if let Ok(v) = value.get::<bool>() {
+                key_file.set_boolean(GROUP, name, v);
+            } else if let Ok(v) = value.get::<f64>() {
+                key_file.set_double(GROUP, name, v);
+            } else if let Ok(v) = value.get::<String>() {
+                key_file.set_string(GROUP, name, &v);
+            } else if let Some((_, v)) = glib::EnumValue::from_value(&value) {
+                key_file.set_string(GROUP, name, v.nick());
+            }
+        }
+        let path = config_path();
+        let result = std::fs::create_dir_all(path.parent().unwrap())
+            .map_err(|e| e.to_string())
+            .and_then(|()| key_file.save_to_file(&path).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            eprintln!("Failed to save preferences: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(ini: &str) -> Preferences {
+        let prefs: Preferences = glib::Object::new();
+        let key_file = glib::KeyFile::new();
+        key_file
+            .load_from_data(&format!("[{GROUP}]\n{ini}"), glib::KeyFileFlags::NONE)
+            .unwrap();
+        prefs.read_from(&key_file);
+        prefs
+    }
+
+    #[test]
+    fn reads_margin_within_bounds() {
+        assert_eq!(read("pdf-margin=20\n").pdf_margin(), 20.0);
+        assert_eq!(read("pdf-margin=50\n").pdf_margin(), 50.0);
+    }
+
+    #[test]
+    fn skips_out_of_range_values() {
+        let prefs = read("pdf-margin=500\nmath=false\n");
+        assert_eq!(prefs.pdf_margin(), 15.0);
+        assert!(!prefs.math());
+        assert_eq!(read("pdf-margin=-1\n").pdf_margin(), 15.0);
+    }
+}
@@ -0,0 +1,1003 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use gtk4::gdk::RGBA;
+use gtk4::{gio, glib};
+use gtk4::prelude::*;
+use pulldown_cmark::{
+    html, BlockQuoteKind, CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd,
+};
+
+use crate::front_matter::{self, FrontMatter, Value};
+
+/// Fenced code block languages drawn as diagrams by Mermaid.
+const DIAGRAM_LANGUAGES: &[&str] = &["mermaid"];
+
+/// File name of the bundled Mermaid library in the data directories.
+const MERMAID_SCRIPT: &str = "mermaid.min.js";
+
+/// File name of the bundled MathJax build (TeX input, SVG output) in the data
+/// directories.
+const MATHJAX_SCRIPT: &str = "tex-svg-full.js";
+
+const PREVIEW_CSS_DARK: &str = r#"
+    :root { color-scheme: dark; background: #1a1a1a !important; }
+    html { background: #1a1a1a !important; min-height: 100%; }
+    body { font-family: 'Cantarell','Inter',system-ui,sans-serif; font-size: 15px; line-height: 1.7;
+        padding: 16px 24px; margin: 0; min-height: 100%; color: #e0e0e0; background: #1a1a1a !important; word-wrap: break-word; }
+    h1,h2,h3,h4,h5,h6 { color: #fff; margin-top: 1.2em; margin-bottom: 0.4em; font-weight: 600; }
+    h1 { font-size: 1.8em; border-bottom: 1px solid #444; padding-bottom: 0.3em; }
+    h2 { font-size: 1.5em; border-bottom: 1px solid #3a3a3a; padding-bottom: 0.2em; }
+    h3 { font-size: 1.25em; }
+    p { margin: 0.6em 0; }
+    a { color: #78b9f5; text-decoration: none; }
+    a:hover { text-decoration: underline; }
+    code { font-family: 'JetBrains Mono','Source Code Pro',monospace; background: #1e1e1e; padding: 2px 6px; border-radius: 4px; font-size: 0.9em; }
+    pre { background: #1e1e1e; padding: 14px 18px; border-radius: 8px; overflow-x: auto; border: 1px solid #3a3a3a; }
+    pre code { background: none; padding: 0; }
+    blockquote { border-left: 3px solid #78b9f5; margin: 0.8em 0; padding: 0.4em 1em; color: #b0b0b0; background: #252525; border-radius: 0 6px 6px 0; }
+    .markdown-alert-note { border-left-color: #4493f8; } .markdown-alert-note > .markdown-alert-title { color: #4493f8; }
+    .markdown-alert-tip { border-left-color: #3fb950; } .markdown-alert-tip > .markdown-alert-title { color: #3fb950; }
+    .markdown-alert-important { border-left-color: #ab7df8; } .markdown-alert-important > .markdown-alert-title { color: #ab7df8; }
+    .markdown-alert-warning { border-left-color: #d29922; } .markdown-alert-warning > .markdown-alert-title { color: #d29922; }
+    .markdown-alert-caution { border-left-color: #f85149; } .markdown-alert-caution > .markdown-alert-title { color: #f85149; }
+    ul,ol { padding-left: 1.8em; }
+    li { margin: 0.25em 0; }
+    hr { border: none; border-top: 1px solid #444; margin: 1.5em 0; }
+    table { border-collapse: collapse; width: 100%; margin: 1em 0; }
+    th,td { border: 1px solid #444; padding: 8px 12px; text-align: left; }
+    th { background: #333; font-weight: 600; }
+    img { max-width: 100%; border-radius: 6px; }
+    strong { color: #f0f0f0; }
+    em { color: #d0d0d0; }
+    .placeholder { color: #a8a8a8; text-align: center; margin-top: 2em; }
+"#;
+
+/// Layout of alert titles; their colors come with the theme.
+const ALERT_CSS: &str = r#"
+    blockquote[class^="markdown-alert-"] { color: inherit; }
+    .markdown-alert-title { display: flex; align-items: center; gap: 0.5em; font-weight: 600; }
+    .markdown-alert-title svg { width: 1em; height: 1em; flex: none; }
+"#;
+
+/// The front matter card at the top of the preview.
+const PROPERTIES_CSS: &str = r#"
+    .mv-properties { margin: 0 0 1.5em; padding: 0.6em 1em; border: 1px solid rgba(127, 127, 127, 0.35); border-radius: 8px; background: rgba(127, 127, 127, 0.08); }
+    .mv-properties summary { font-weight: 600; cursor: pointer; }
+    .mv-properties dl { display: grid; grid-template-columns: max-content 1fr; gap: 0.3em 1.5em; margin: 0.75em 0 0.25em; }
+    .mv-properties dt { opacity: 0.7; }
+    .mv-properties dd { margin: 0; }
+    .mv-tag { display: inline-block; margin: 0 0.3em 0.2em 0; padding: 0 0.6em; border-radius: 1em; background: rgba(127, 127, 127, 0.2); font-size: 0.9em; }
+"#;
+
+/// Admonition types of MkDocs' `!!! type` blocks mapped to the GitHub alert
+/// they are drawn as; other types are drawn as notes.
+const ADMONITION_KINDS: &[(&str, BlockQuoteKind)] = &[
+    ("tip", BlockQuoteKind::Tip),
+    ("hint", BlockQuoteKind::Tip),
+    ("success", BlockQuoteKind::Tip),
+    ("check", BlockQuoteKind::Tip),
+    ("done", BlockQuoteKind::Tip),
+    ("important", BlockQuoteKind::Important),
+    ("question", BlockQuoteKind::Important),
+    ("help", BlockQuoteKind::Important),
+    ("faq", BlockQuoteKind::Important),
+    ("warning", BlockQuoteKind::Warning),
+    ("caution", BlockQuoteKind::Warning),
+    ("attention", BlockQuoteKind::Warning),
+    ("danger", BlockQuoteKind::Caution),
+    ("error", BlockQuoteKind::Caution),
+    ("failure", BlockQuoteKind::Caution),
+    ("fail", BlockQuoteKind::Caution),
+    ("missing", BlockQuoteKind::Caution),
+    ("bug", BlockQuoteKind::Caution),
+];
+
+const PRINT_CSS: &str = r#"
+    @media print {
+        html, body, :root { margin: 0 !important; padding: 0 !important; border: none !important; outline: none !important; }
+        body { padding: 16px 24px !important; }
+        img { margin: 0 !important; padding: 0 !important; border: none !important; outline: none !important; box-shadow: none !important; }
+    }
+"#;
+
+const PREVIEW_CSS_LIGHT: &str = r#"
+    :root { color-scheme: light; background: #fafafa !important; }
+    html { background: #fafafa !important; min-height: 100%; }
+    body { font-family: 'Cantarell','Inter',system-ui,sans-serif; font-size: 15px; line-height: 1.7;
+        padding: 16px 24px; margin: 0; min-height: 100%; color: #241f31; background: #fafafa !important; word-wrap: break-word; }
+    h1,h2,h3,h4,h5,h6 { color: #1c1c1c; margin-top: 1.2em; margin-bottom: 0.4em; font-weight: 600; }
+    h1 { font-size: 1.8em; border-bottom: 1px solid #c0bfc4; padding-bottom: 0.3em; }
+    h2 { font-size: 1.5em; border-bottom: 1px solid #d1d0d5; padding-bottom: 0.2em; }
+    h3 { font-size: 1.25em; }
+    p { margin: 0.6em 0; }
+    a { color: #1c71d8; text-decoration: none; }
+    a:hover { text-decoration: underline; }
+    code { font-family: 'JetBrains Mono','Source Code Pro',monospace; background: #ebebeb; padding: 2px 6px; border-radius: 4px; font-size: 0.9em; color: #1c1c1c; }
+    pre { background: #ebebeb; padding: 14px 18px; border-radius: 8px; overflow-x: auto; border: 1px solid #d1d0d5; }
+    pre code { background: none; padding: 0; }
+    blockquote { border-left: 3px solid #1c71d8; margin: 0.8em 0; padding: 0.4em 1em; color: #56565c; background: #f0eff1; border-radius: 0 6px 6px 0; }
+    .markdown-alert-note { border-left-color: #0969da; } .markdown-alert-note > .markdown-alert-title { color: #0969da; }
+    .markdown-alert-tip { border-left-color: #1a7f37; } .markdown-alert-tip > .markdown-alert-title { color: #1a7f37; }
+    .markdown-alert-important { border-left-color: #8250df; } .markdown-alert-important > .markdown-alert-title { color: #8250df; }
+    .markdown-alert-warning { border-left-color: #9a6700; } .markdown-alert-warning > .markdown-alert-title { color: #9a6700; }
+    .markdown-alert-caution { border-left-color: #d1242f; } .markdown-alert-caution > .markdown-alert-title { color: #d1242f; }
+    ul,ol { padding-left: 1.8em; }
+    li { margin: 0.25em 0; }
+    hr { border: none; border-top: 1px solid #c0bfc4; margin: 1.5em 0; }
+    table { border-collapse: collapse; width: 100%; margin: 1em 0; }
+    th,td { border: 1px solid #c0bfc4; padding: 8px 12px; text-align: left; }
+    th { background: #ebe9ed; font-weight: 600; }
+    img { max-width: 100%; border-radius: 6px; }
+    strong { color: #1c1c1c; }
+    em { color: #363536; }
+    .placeholder { color: #6b6b6b; text-align: center; margin-top: 2em; }
+"#;
+
+/// Loaded once with the preview page. Later renders are pushed through
+/// `markview.setContent`, which only swaps the top-level nodes that changed so
+/// scroll position, selection and loaded images survive edits.
+const PREVIEW_BOOTSTRAP_JS: &str = r#"
+    (function () {
+        let suppressScrollUntil = 0;
+        let scrollFrame = 0;
+
+        // Top-level blocks match when only their source line annotations moved;
+        // the old node is kept and just takes over the new line numbers.
+        // Blocks holding drawn diagrams are compared by the markup they had
+        // before drawing.
+        function sameNode(a, b) {
+            if (a.nodeType === Node.ELEMENT_NODE && b.nodeType === Node.ELEMENT_NODE
+                && a.classList.contains('mv-block') && b.classList.contains('mv-block')) {
+                if ((a.dataset.html ?? a.innerHTML) !== b.innerHTML) {
+                    return false;
+                }
+                a.dataset.lineStart = b.dataset.lineStart;
+                a.dataset.lineEnd = b.dataset.lineEnd;
+                return true;
+            }
+            return a.isEqualNode(b);
+        }
+
+        function blocks() {
+            return document.querySelectorAll('#markview-content > .mv-block');
+        }
+
+        // Fractional source line shown at the top of the viewport.
+        function topLine() {
+            const y = window.scrollY;
+            for (const block of blocks()) {
+                const rect = block.getBoundingClientRect();
+                const top = rect.top + y;
+                if (top + rect.height > y) {
+                    const start = Number(block.dataset.lineStart);
+                    const end = Number(block.dataset.lineEnd) + 1;
+                    const frac = rect.height > 0 ? Math.max(0, (y - top) / rect.height) : 0;
+                    return start + frac * (end - start);
+                }
+            }
+            return -1;
+        }
+
+        window.addEventListener('scroll', function () {
+            if (Date.now() < suppressScrollUntil || scrollFrame) {
+                return;
+            }
+            scrollFrame = requestAnimationFrame(function () {
+                scrollFrame = 0;
+                const atEnd = window.innerHeight + window.scrollY
+                    >= document.documentElement.scrollHeight - 1;
+                const line = atEnd ? -1 : topLine();
+                window.webkit.messageHandlers.markviewScroll.postMessage(line);
+            });
+        });
+
+        // Double-clicking rendered text moves the editor cursor to its source.
+        document.addEventListener('dblclick', function (event) {
+            const block = event.target.closest && event.target.closest('.mv-block');
+            if (!block) {
+                return;
+            }
+            const start = Number(block.dataset.lineStart);
+            const end = Number(block.dataset.lineEnd) + 1;
+            const rect = block.getBoundingClientRect();
+            const frac = rect.height > 0 ? (event.clientY - rect.top) / rect.height : 0;
+            const line = Math.min(end - 1, Math.floor(start + Math.max(0, frac) * (end - start)));
+            window.webkit.messageHandlers.markviewJump.postMessage(line);
+        });
+
+        function patch(container, html) {
+            const next = document.createElement('div');
+            next.innerHTML = html;
+            const oldNodes = Array.from(container.childNodes);
+            const newNodes = Array.from(next.childNodes);
+            let start = 0;
+            while (start < oldNodes.length && start < newNodes.length
+                   && sameNode(oldNodes[start], newNodes[start])) {
+                start++;
+            }
+            let oldEnd = oldNodes.length;
+            let newEnd = newNodes.length;
+            while (oldEnd > start && newEnd > start
+                   && sameNode(oldNodes[oldEnd - 1], newNodes[newEnd - 1])) {
+                oldEnd--;
+                newEnd--;
+            }
+            const anchor = oldEnd < oldNodes.length ? oldNodes[oldEnd] : null;
+            for (let i = start; i < oldEnd; i++) {
+                container.removeChild(oldNodes[i]);
+            }
+            for (let i = start; i < newEnd; i++) {
+                container.insertBefore(newNodes[i], anchor);
+            }
+        }
+        window.markview = {
+            setContent: function (html) {
+                patch(document.getElementById('markview-content'), html);
+                if (window.markviewDiagrams) {
+                    markviewDiagrams.render();
+                }
+                if (window.markviewMath) {
+                    markviewMath.render();
+                }
+            },
+            setTheme: function (css, dark) {
+                document.getElementById('markview-theme').textContent = css;
+                if (window.markviewDiagrams) {
+                    markviewDiagrams.setDark(dark);
+                }
+            },
+            // Scrolls so that fractional source `line` is at the top; a
+            // negative line scrolls to the end of the document.
+            scrollToLine: function (line) {
+                suppressScrollUntil = Date.now() + 200;
+                if (line < 0) {
+                    window.scrollTo(0, document.documentElement.scrollHeight);
+                    return;
+                }
+                let target = null;
+                for (const block of blocks()) {
+                    if (Number(block.dataset.lineStart) > line) {
+                        break;
+                    }
+                    target = block;
+                }
+                if (!target) {
+                    window.scrollTo(0, 0);
+                    return;
+                }
+                const start = Number(target.dataset.lineStart);
+                const end = Number(target.dataset.lineEnd) + 1;
+                const frac = Math.min(1, (line - start) / (end - start));
+                const rect = target.getBoundingClientRect();
+                window.scrollTo(0, rect.top + window.scrollY + frac * rect.height);
+            },
+        };
+    })();
+"#;
+
+/// Draws `<pre class="mermaid">` blocks with the bundled Mermaid library.
+/// `markviewReady` resolves once the diagrams present at load are drawn, so
+/// printing can wait for them.
+const DIAGRAMS_JS: &str = r#"
+    window.markviewDiagrams = (function () {
+        let dark = false;
+
+        function configure() {
+            mermaid.initialize({ startOnLoad: false, theme: dark ? 'dark' : 'default' });
+        }
+
+        // Draws every diagram not drawn yet, keeping its source for redrawing
+        // and the markup of its block for the preview's diffing.
+        function render() {
+            const nodes = Array.from(document.querySelectorAll('pre.mermaid:not([data-processed])'));
+            if (nodes.length === 0) {
+                return Promise.resolve();
+            }
+            for (const node of nodes) {
+                const block = node.closest('.mv-block');
+                if (block && block.dataset.html === undefined) {
+                    block.dataset.html = block.innerHTML;
+                }
+                node.dataset.source ??= node.textContent;
+            }
+            return mermaid.run({ nodes, suppressErrors: true });
+        }
+
+        return {
+            init: function (isDark) {
+                dark = isDark;
+                configure();
+                const loaded = new Promise(function (resolve) {
+                    window.addEventListener('load', resolve);
+                });
+                window.markviewReady = Promise.all([window.markviewReady, loaded]).then(render);
+            },
+            render: render,
+            setDark: function (isDark) {
+                if (isDark === dark) {
+                    return;
+                }
+                dark = isDark;
+                configure();
+                for (const node of document.querySelectorAll('pre.mermaid[data-processed]')) {
+                    node.textContent = node.dataset.source;
+                    node.removeAttribute('data-processed');
+                }
+                render();
+            },
+        };
+    })();
+"#;
+
+/// MathJax configuration, which must be set before the library loads. Math is
+/// typeset by `MATH_JS` from the spans pulldown-cmark emits rather than by
+/// scanning the page for delimiters.
+const MATHJAX_CONFIG_JS: &str = r#"
+    window.MathJax = {
+        startup: { typeset: false },
+        options: { enableMenu: false },
+        svg: { fontCache: 'local' },
+    };
+"#;
+
+/// Typesets `<span class="math">` elements with the bundled MathJax.
+/// Chained onto `markviewReady` like the diagrams.
+const MATH_JS: &str = r#"
+    window.markviewMath = (function () {
+        // Typesets every math span not typeset yet, keeping the markup of its
+        // block for the preview's diffing.
+        function render() {
+            const nodes = Array.from(document.querySelectorAll('span.math:not([data-typeset])'));
+            return MathJax.startup.promise.then(function () {
+                return Promise.all(nodes.map(function (node) {
+                    const block = node.closest('.mv-block');
+                    if (block && block.dataset.html === undefined) {
+                        block.dataset.html = block.innerHTML;
+                    }
+                    node.dataset.typeset = '';
+                    const display = node.classList.contains('math-display');
+                    return MathJax.tex2svgPromise(node.textContent, { display: display })
+                        .then(function (math) {
+                            node.replaceChildren(math);
+                        })
+                        .catch(function (e) {
+                            console.error('Could not typeset math', e);
+                        });
+                }));
+            });
+        }
+
+        return {
+            init: function () {
+                const loaded = new Promise(function (resolve) {
+                    window.addEventListener('load', resolve);
+                });
+                window.markviewReady = Promise.all([window.markviewReady, loaded]).then(render);
+            },
+            render: render,
+        };
+    })();
+"#;
+
+pub const PLACEHOLDER_HTML: &str = "<p class='placeholder'>Start typing markdown on the left…</p>";
+
+/// Renders `text` to HTML. With `annotate`, every top-level block is wrapped
+/// in a `<div class="mv-block">` annotated with its 0-based source line range
+/// so the preview can be scrolled in step with the editor; exports leave it
+/// out. Without `math`, dollar signs are literal text.
+pub fn render_markdown(text: &str, annotate: bool, math: bool) -> String {
+    let (text, admonition_titles) = convert_admonitions(text);
+    let text = text.as_ref();
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let line_of = |offset: usize| line_starts.partition_point(|&start| start <= offset) - 1;
+    let open_block = |range: &std::ops::Range<usize>| {
+        Event::Html(CowStr::from(format!(
+            "<div class=\"mv-block\" data-line-start=\"{}\" data-line-end=\"{}\">",
+            line_of(range.start),
+            line_of(range.end.saturating_sub(1).max(range.start)),
+        )))
+    };
+
+    let mut events = Vec::new();
+    let mut depth = 0usize;
+    // The wrapper being filled: the index of its opening tag in `events`,
+    // written once the source range it covers is known.
+    let mut block: Option<(usize, Range<usize>)> = None;
+    // Raw HTML elements opened in the wrapper and not closed yet. A
+    // `<details>` around Markdown spans several blocks, which have to share
+    // one wrapper or the browser would close the element early.
+    let mut open_elements = 0;
+    let mut options = Options::all();
+    options.set(Options::ENABLE_MATH, math);
+    for (event, range) in diagram_blocks(Parser::new_ext(text, options).into_offset_iter()) {
+        if annotate && depth == 0 && block.is_none() {
+            block = Some((events.len(), range.clone()));
+            events.push(Event::Html(CowStr::Borrowed("")));
+        }
+        if annotate && let Event::Html(ref html) | Event::InlineHtml(ref html) = event {
+            open_elements += html_balance(html);
+        }
+        let block_done = match event {
+            Event::Start(ref tag) => {
+                // Front matter is shown as a card in the preview only;
+                // exports carry it in their `<head>` instead.
+                if annotate && matches!(tag, Tag::MetadataBlock(_))
+                    && let Some(front_matter) = front_matter::parse(text)
+                {
+                    events.push(Event::Html(CowStr::from(properties_card(&front_matter))));
+                }
+                depth += 1;
+                let title = match tag {
+                    Tag::BlockQuote(Some(kind)) => {
+                        alert_title(*kind, admonition_titles.get(&line_of(range.start)))
+                    }
+                    _ => None,
+                };
+                events.push(event);
+                events.extend(title);
+                false
+            }
+            Event::End(_) => {
+                depth -= 1;
+                events.push(event);
+                depth == 0
+            }
+            _ => {
+                events.push(event);
+                depth == 0
+            }
+        };
+        if block_done && let Some((_, ref mut block_range)) = block {
+            block_range.end = range.end;
+            if open_elements <= 0 {
+                let (index, block_range) = block.take().unwrap();
+                events[index] = open_block(&block_range);
+                events.push(Event::Html(CowStr::Borrowed("</div>")));
+                open_elements = 0;
+            }
+        }
+    }
+    // HTML left open at the end of the document.
+    if let Some((index, block_range)) = block {
+        events[index] = open_block(&block_range);
+        events.push(Event::Html(CowStr::Borrowed("</div>")));
+    }
+
+    let mut html_out = String::new();
+    html::push_html(&mut html_out, events.into_iter());
+    html_out
+}
+
+/// Elements `html` opens minus those it closes. Void elements, self-closing
+/// tags and comments count for nothing.
+fn html_balance(html: &str) -> i32 {
+    const VOID_ELEMENTS: &[&str] = &[
+        "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track", "wbr",
+    ];
+    let mut balance = 0;
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        if let Some(comment) = rest.strip_prefix("!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let closing = rest.starts_with('/');
+        let name: String = rest[usize::from(closing)..]
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '-')
+            .collect();
+        if name.is_empty() {
+            continue;
+        }
+        let tag = &rest[..rest.find('>').unwrap_or(rest.len())];
+        if closing {
+            balance -= 1;
+        } else if !tag.ends_with('/') && !VOID_ELEMENTS.contains(&name.to_ascii_lowercase().as_str()) {
+            balance += 1;
+        }
+    }
+    balance
+}
+
+/// Rewrites MkDocs admonitions into GitHub alerts with the same number of
+/// lines, so source line annotations still match:
+///
+/// ```text
+/// !!! warning "Mind the gap"        > [!WARNING]
+///     Indented content.        →    > Indented content.
+/// ```
+///
+/// Returns the rewritten text and each admonition's title by the line it
+/// starts on; an empty title (`!!! note ""`) hides the title bar.
+fn convert_admonitions(text: &str) -> (Cow<'_, str>, HashMap<usize, String>) {
+    let mut titles = HashMap::new();
+    if !text.contains("!!!") {
+        return (Cow::Borrowed(text), titles);
+    }
+    let lines: Vec<&str> = text.split('\n').collect();
+    let mut out = Vec::with_capacity(lines.len());
+    let mut fence: Option<&str> = None;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim_start();
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            }
+        } else if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fence = Some(&trimmed[..3]);
+        } else if let Some((kind, title)) = parse_admonition(line) {
+            titles.insert(i, title);
+            out.push(Cow::Owned(format!("> [!{}]", alert_name(kind).to_uppercase())));
+            i += 1;
+            // The body is every following indented line, and blank lines
+            // followed by more of them.
+            while i < lines.len() {
+                let body = lines[i];
+                let content = body
+                    .strip_prefix("    ")
+                    .or_else(|| body.strip_prefix('\t'));
+                if let Some(content) = content {
+                    out.push(Cow::Owned(format!("> {content}")));
+                } else if body.trim().is_empty()
+                    && lines[i..]
+                        .iter()
+                        .find(|l| !l.trim().is_empty())
+                        .is_some_and(|l| l.starts_with("    ") || l.starts_with('\t'))
+                {
+                    out.push(Cow::Borrowed(">"));
+                } else {
+                    break;
+                }
+                i += 1;
+            }
+            continue;
+        }
+        out.push(Cow::Borrowed(line));
+        i += 1;
+    }
+    (Cow::Owned(out.join("\n")), titles)
+}
+
+/// Parses `!!! type "Optional title"` into an alert kind and title, which
+/// defaults to the capitalized type.
+fn parse_admonition(line: &str) -> Option<(BlockQuoteKind, String)> {
+    let rest = line.strip_prefix("!!! ")?.trim();
+    let (type_, title) = match rest.split_once(char::is_whitespace) {
+        Some((type_, title)) => (type_, Some(title.trim())),
+        None => (rest, None),
+    };
+    if type_.is_empty() || !type_.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return None;
+    }
+    let type_ = type_.to_lowercase();
+    let kind = ADMONITION_KINDS
+        .iter()
+        .find(|(name, _)| *name == type_)
+        .map_or(BlockQuoteKind::Note, |(_, kind)| *kind);
+    let title = match title {
+        Some(title) => title.strip_prefix('"')?.strip_suffix('"')?.to_string(),
+        None => {
+            let mut chars = type_.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect())
+                .unwrap_or_default()
+        }
+    };
+    Some((kind, title))
+}
+
+fn alert_name(kind: BlockQuoteKind) -> &'static str {
+    match kind {
+        BlockQuoteKind::Note => "Note",
+        BlockQuoteKind::Tip => "Tip",
+        BlockQuoteKind::Important => "Important",
+        BlockQuoteKind::Warning => "Warning",
+        BlockQuoteKind::Caution => "Caution",
+    }
+}
+
+/// Title bar with an icon opening an alert of `kind`, named after the kind
+/// unless an admonition gave its own `title`.
+fn alert_title(kind: BlockQuoteKind, title: Option<&String>) -> Option<Event<'static>> {
+    let title = title.map_or(alert_name(kind), String::as_str);
+    if title.is_empty() {
+        return None;
+    }
+    let icon = match kind {
+        // Circled "i".
+        BlockQuoteKind::Note => r#"<circle cx="8" cy="8" r="6.5"/><path d="M8 7v4.5M8 4.5v.5"/>"#,
+        // Light bulb.
+        BlockQuoteKind::Tip => {
+            r#"<path d="M8 1.5a4.5 4.5 0 0 0-2.5 8.2v1.8h5V9.7A4.5 4.5 0 0 0 8 1.5zM6 14.5h4"/>"#
+        }
+        // Speech bubble with "!".
+        BlockQuoteKind::Important => {
+            r#"<path d="M2.5 2.5h11v8.5H7l-3 3v-3H2.5zM8 4.5v3.5M8 9.5v.5"/>"#
+        }
+        // Triangle with "!".
+        BlockQuoteKind::Warning => r#"<path d="M8 1.5l6.5 12h-13zM8 6v4M8 11.5v.5"/>"#,
+        // Octagon with "!".
+        BlockQuoteKind::Caution => {
+            r#"<path d="M5.3 1.5h5.4l3.8 3.8v5.4l-3.8 3.8H5.3l-3.8-3.8V5.3zM8 4.5v4.5M8 10.5v.5"/>"#
+        }
+    };
+    Some(Event::Html(CowStr::from(format!(
+        "<p class=\"markdown-alert-title\"><svg viewBox=\"0 0 16 16\" fill=\"none\" stroke=\"currentColor\" \
+         stroke-width=\"1.5\" stroke-linecap=\"round\" stroke-linejoin=\"round\" aria-hidden=\"true\">{icon}</svg>{}</p>",
+        glib::markup_escape_text(title)
+    ))))
+}
+
+/// Collapsible card listing the front matter fields, headed by its title.
+fn properties_card(front_matter: &FrontMatter) -> String {
+    let title = front_matter.title();
+    let mut rows = String::new();
+    for field in &front_matter.fields {
+        if title.is_some() && field.key.eq_ignore_ascii_case("title") {
+            continue;
+        }
+        let value = match &field.value {
+            Value::Text(text) if text.is_empty() => continue,
+            Value::Text(text) => glib::markup_escape_text(text).to_string(),
+            Value::List(items) => items
+                .iter()
+                .map(|item| format!("<span class=\"mv-tag\">{}</span>", glib::markup_escape_text(item)))
+                .collect(),
+        };
+        let mut key = field.key.replace(['_', '-'], " ");
+        if let Some(first) = key.get(..1) {
+            key.replace_range(..1, &first.to_uppercase());
+        }
+        rows.push_str(&format!("<dt>{}</dt><dd>{value}</dd>", glib::markup_escape_text(&key)));
+    }
+    format!(
+        "<details class=\"mv-properties\" open><summary>{}</summary><dl>{rows}</dl></details>",
+        glib::markup_escape_text(title.as_deref().unwrap_or("Properties"))
+    )
+}
+
+fn is_diagram_language(info: &str) -> bool {
+    info.split_whitespace()
+        .next()
+        .is_some_and(|lang| DIAGRAM_LANGUAGES.contains(&lang))
+}
+
+/// Replaces fenced code blocks in a diagram language with a
+/// `<pre class="mermaid">` holding their source, for `DIAGRAMS_JS` to draw.
+/// Without the Mermaid library they still read as code blocks.
+fn diagram_blocks<'a>(
+    events: impl Iterator<Item = (Event<'a>, Range<usize>)>,
+) -> impl Iterator<Item = (Event<'a>, Range<usize>)> {
+    let mut diagram: Option<(String, Range<usize>)> = None;
+    events.filter_map(move |(event, range)| match event {
+        Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref info))) if is_diagram_language(info) => {
+            diagram = Some((String::new(), range));
+            None
+        }
+        Event::Text(ref text) if diagram.is_some() => {
+            if let Some((source, _)) = diagram.as_mut() {
+                source.push_str(text);
+            }
+            None
+        }
+        Event::End(TagEnd::CodeBlock) if diagram.is_some() => {
+            let (source, range) = diagram.take()?;
+            let html = format!("<pre class=\"mermaid\">{}</pre>\n", glib::markup_escape_text(&source));
+            Some((Event::Html(CowStr::from(html)), range))
+        }
+        _ => Some((event, range)),
+    })
+}
+
+/// Finds the bundled script `name` in the `markview` application data
+/// directories, next to the executable for release archives, or in the
+/// source tree's `data` directory for development builds.
+fn find_bundled_script(name: &str) -> Option<PathBuf> {
+    let mut candidates: Vec<PathBuf> = std::iter::once(glib::user_data_dir())
+        .chain(glib::system_data_dirs())
+        .map(|dir| dir.join("markview").join(name))
+        .collect();
+    if let Ok(exe) = std::env::current_exe()
+        && let Some(dir) = exe.parent()
+    {
+        candidates.push(dir.join(name));
+    }
+    candidates.push(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("data").join(name));
+    let path = candidates.into_iter().find(|path| path.is_file());
+    if path.is_none() {
+        eprintln!("{name} not found; its content is shown as source");
+    }
+    path
+}
+
+/// A library installed alongside MarkView, looked up on first use.
+struct BundledScript {
+    name: &'static str,
+    path: OnceLock<Option<PathBuf>>,
+    content: OnceLock<Option<String>>,
+}
+
+static MERMAID: BundledScript = BundledScript::new(MERMAID_SCRIPT);
+static MATHJAX: BundledScript = BundledScript::new(MATHJAX_SCRIPT);
+
+impl BundledScript {
+    const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            path: OnceLock::new(),
+            content: OnceLock::new(),
+        }
+    }
+
+    /// `<script>` element running the library: inline for exports, which
+    /// must stand on their own, or by `file://` URI for the preview, so the
+    /// library is not copied into every page load.
+    fn element(&self, inline: bool) -> Option<String> {
+        let path = self.path.get_or_init(|| find_bundled_script(self.name)).as_ref()?;
+        if !inline {
+            let uri = glib::filename_to_uri(path, None).ok()?;
+            return Some(format!("<script src=\"{uri}\"></script>"));
+        }
+        let content = self.content.get_or_init(|| {
+            // A closing script tag inside the library would end its inline
+            // element early.
+            std::fs::read_to_string(path)
+                .ok()
+                .map(|script| script.replace("</script", "<\\/script"))
+        });
+        Some(format!("<script>{}</script>", content.as_ref()?))
+    }
+}
+
+/// Scripts that draw the diagrams in `body`, to be placed after it. Empty
+/// when `body` has none, unless for the live `preview` where they may be
+/// added later, or when the Mermaid library is not installed.
+pub fn diagram_scripts(body: &str, dark: bool, preview: bool) -> String {
+    if !preview && !body.contains("<pre class=\"mermaid\">") {
+        return String::new();
+    }
+    let Some(library) = MERMAID.element(!preview) else {
+        return String::new();
+    };
+    format!("{library}<script>{DIAGRAMS_JS} markviewDiagrams.init({dark});</script>")
+}
+
+/// Scripts that typeset the math in `body`, to be placed after it. Empty under
+/// the same conditions as `diagram_scripts`.
+pub fn math_scripts(body: &str, preview: bool) -> String {
+    if !preview && !body.contains("<span class=\"math ") {
+        return String::new();
+    }
+    let Some(library) = MATHJAX.element(!preview) else {
+        return String::new();
+    };
+    format!("<script>{MATHJAX_CONFIG_JS}</script>{library}<script>{MATH_JS} markviewMath.init();</script>")
+}
+
+/// A heading of the document outline.
+#[derive(Clone, PartialEq)]
+pub struct Heading {
+    /// 1 to 6.
+    pub level: u32,
+    pub title: String,
+    /// 0-based source line the heading starts on.
+    pub line: i32,
+}
+
+/// Every heading in `text`, in document order, parsed the same way as
+/// `render_markdown` with the same `math` setting.
+pub fn outline(text: &str, math: bool) -> Vec<Heading> {
+    let (text, _) = convert_admonitions(text);
+    let text = text.as_ref();
+    let mut options = Options::all();
+    options.set(Options::ENABLE_MATH, math);
+    let mut headings = Vec::new();
+    let mut current: Option<Heading> = None;
+    for (event, range) in Parser::new_ext(text, options).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                current = Some(Heading {
+                    level: level as u32,
+                    title: String::new(),
+                    line: text[..range.start].matches('\n').count() as i32,
+                });
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some(mut heading) = current.take() {
+                    heading.title = heading.title.trim().to_string();
+                    headings.push(heading);
+                }
+            }
+            Event::Text(t) | Event::Code(t) | Event::InlineMath(t) => {
+                if let Some(ref mut heading) = current {
+                    heading.title.push_str(&t);
+                }
+            }
+            _ => {}
+        }
+    }
+    headings
+}
+
+/// Text of the first heading in `text`, for use as a document title.
+pub fn first_heading(text: &str) -> Option<String> {
+    let mut title: Option<String> = None;
+    for event in Parser::new_ext(text, Options::all()) {
+        match event {
+            Event::Start(Tag::Heading { .. }) => title = Some(String::new()),
+            Event::End(TagEnd::Heading(_)) if title.is_some() => break,
+            Event::Text(t) | Event::Code(t) => {
+                if let Some(ref mut title) = title {
+                    title.push_str(&t);
+                }
+            }
+            _ => {}
+        }
+    }
+    title
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+}
+
+/// Title for `text`: the `title` of its front matter, or else its first
+/// heading.
+pub fn document_title(text: &str) -> Option<String> {
+    front_matter::parse(text)
+        .and_then(|front_matter| front_matter.title())
+        .or_else(|| first_heading(text))
+}
+
+/// `<title>` and `<meta>` elements for an exported page of `text`, from its
+/// front matter and first heading.
+pub fn document_head(text: &str) -> String {
+    let mut head = document_title(text)
+        .map(|t| format!("<title>{}</title>", glib::markup_escape_text(&t)))
+        .unwrap_or_default();
+    if let Some(front_matter) = front_matter::parse(text) {
+        let tags = front_matter.list("tags");
+        let keywords = if tags.is_empty() { front_matter.list("keywords") } else { tags };
+        let meta = [
+            ("author", front_matter.text("author")),
+            ("description", front_matter.text("description")),
+            ("date", front_matter.text("date")),
+            ("keywords", (!keywords.is_empty()).then(|| keywords.join(", "))),
+        ];
+        for (name, content) in meta {
+            if let Some(content) = content {
+                head.push_str(&format!(
+                    "<meta name=\"{name}\" content=\"{}\">",
+                    glib::markup_escape_text(&content)
+                ));
+            }
+        }
+    }
+    head
+}
+
+/// A page around `body`; `head` holds extra `<head>` elements such as those
+/// from `document_head`.
+pub fn build_html_page(body: &str, head: &str, dark: bool) -> String {
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">{}<style id=\"markview-theme\">{}</style><style>{}{}{}</style></head><body>{}</body></html>",
+        head, theme_css(dark), ALERT_CSS, PROPERTIES_CSS, PRINT_CSS, body
+    )
+}
+
+pub fn theme_css(dark: bool) -> &'static str {
+    if dark { PREVIEW_CSS_DARK } else { PREVIEW_CSS_LIGHT }
+}
+
+/// The live preview page: `build_html_page` plus the bootstrap script and the
+/// container that `markview.setContent` updates in place.
+pub fn build_preview_page(body: &str, dark: bool, math: bool) -> String {
+    build_html_page(
+        &format!(
+            "<div id=\"markview-content\">{}</div>{}{}<script>{}</script>",
+            body,
+            diagram_scripts(body, dark, true),
+            if math { math_scripts(body, true) } else { String::new() },
+            PREVIEW_BOOTSTRAP_JS
+        ),
+        "",
+        dark,
+    )
+}
+
+pub fn background_color(dark: bool) -> RGBA {
+    let (r, g, b) = if dark { (0.102, 0.102, 0.102) } else { (0.98, 0.98, 0.98) };
+    RGBA::new(r, g, b, 1.0)
+}
+
+pub fn base_uri_for_preview(current_file: Option<&gio::File>) -> Option<String> {
+    if let Some(file) = current_file
+        && let Some(parent) = file.parent()
+    {
+        let mut uri = parent.uri().to_string();
+        if !uri.ends_with('/') {
+            uri.push('/');
+        }
+        return Some(uri);
+    }
+    std::env::current_dir()
+        .ok()
+        .and_then(|path| path.canonicalize().ok())
+        .map(|path| format!("file://{}/", path.to_string_lossy()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_html_spanning_blocks_in_one_wrapper() {
+        let html = render_markdown(
+            "# Notes\n\n<details>\n<summary>More</summary>\n\nFirst paragraph.\n\nSecond paragraph.\n\n</details>\n\nAfter.\n",
+            true,
+            false,
+        );
+        let blocks: Vec<&str> = html.split("<div class=\"mv-block\"").skip(1).collect();
+        assert_eq!(blocks.len(), 3);
+        assert!(blocks[1].starts_with(" data-line-start=\"2\" data-line-end=\"9\">"));
+        assert!(blocks[1].contains("<p>First paragraph.</p>"));
+        assert!(blocks[1].contains("<p>Second paragraph.</p>"));
+        assert!(blocks[1].trim_end().ends_with("</details>\n</div>"));
+        assert!(blocks[2].contains("<p>After.</p>"));
+    }
+
+    #[test]
+    fn parses_admonition_headers() {
+        assert_eq!(
+            parse_admonition("!!! warning \"Mind the gap\""),
+            Some((BlockQuoteKind::Warning, "Mind the gap".to_string()))
+        );
+        assert_eq!(parse_admonition("!!! hint"), Some((BlockQuoteKind::Tip, "Hint".to_string())));
+        assert_eq!(parse_admonition("!!! note \"\""), Some((BlockQuoteKind::Note, String::new())));
+        // Unknown types are notes titled after the type.
+        assert_eq!(
+            parse_admonition("!!! abstract"),
+            Some((BlockQuoteKind::Note, "Abstract".to_string()))
+        );
+        assert_eq!(parse_admonition("!!! tip unquoted title"), None);
+        assert_eq!(parse_admonition("!!!tip"), None);
+        assert_eq!(parse_admonition("!!! a.b"), None);
+    }
+
+    #[test]
+    fn converts_admonition_until_dedent() {
+        let (text, titles) =
+            convert_admonitions("Intro\n!!! danger \"Careful\"\n    First.\n\n    Second.\nAfter.\n");
+        assert_eq!(text, "Intro\n> [!CAUTION]\n> First.\n>\n> Second.\nAfter.\n");
+        assert_eq!(titles, HashMap::from([(1, "Careful".to_string())]));
+    }
+
+    #[test]
+    fn converts_admonition_until_blank_line() {
+        let (text, titles) = convert_admonitions("!!! note\n\tBody.\n\nNot in it.\n");
+        assert_eq!(text, "> [!NOTE]\n> Body.\n\nNot in it.\n");
+        assert_eq!(titles, HashMap::from([(0, "Note".to_string())]));
+    }
+
+    #[test]
+    fn leaves_admonitions_in_code_alone() {
+        let text = "```\n!!! note\n    Body.\n```\n";
+        assert_eq!(convert_admonitions(text).0, text);
+    }
+
+    #[test]
+    fn counts_only_elements_left_open() {
+        assert_eq!(html_balance("<div align=\"center\">"), 1);
+        assert_eq!(html_balance("<img src=\"logo.png\"><br/>"), 0);
+        assert_eq!(html_balance("<!-- <div> --><kbd>Ctrl</kbd>"), 0);
+        assert_eq!(html_balance("</div>"), -1);
+    }
+}
@@ -0,0 +1,104 @@
+use std::path::PathBuf;
+
+use gtk4::glib;
+use gtk4::prelude::*;
+
+use crate::prefs::state_dir;
+
+/// Unpinned documents kept in the list; pinned ones are never dropped.
+const MAX_RECENT: usize = 10;
+
+pub struct RecentItem {
+    pub uri: String,
+    pub pinned: bool,
+}
+
+fn recent_path() -> PathBuf {
+    state_dir().join("recent.ini")
+}
+
+/// Recently used documents, pinned ones first, each part most recent first.
+pub fn load() -> Vec<RecentItem> {
+    let key_file = glib::KeyFile::new();
+    if key_file
+        .load_from_file(recent_path(), glib::KeyFileFlags::NONE)
+        .is_err()
+    {
+        return Vec::new();
+    }
+    let mut items: Vec<RecentItem> = key_file
+        .groups()
+        .iter()
+        .filter(|group| group.starts_with("Recent "))
+        .filter_map(|group| {
+            Some(RecentItem {
+                uri: key_file.string(group, "uri").ok()?.to_string(),
+                pinned: key_file.boolean(group, "pinned").unwrap_or(false),
+            })
+        })
+        .collect();
+    // Stable, so the stored order is kept within each part.
+    items.sort_by_key(|item| !item.pinned);
+    items
+}
+
+fn save(items: &[RecentItem]) {
+    let key_file = glib::KeyFile::new();
+    for (i, item) in items.iter().enumerate() {
+        let group = format!("Recent {i}");
+        key_file.set_string(&group, "uri", &item.uri);
+        key_file.set_boolean(&group, "pinned", item.pinned);
+    }
+    let result = std::fs::create_dir_all(state_dir())
+        .map_err(|e| e.to_string())
+        .and_then(|()| key_file.save_to_file(recent_path()).map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        eprintln!("Failed to save recent documents: {e}");
+    }
+}
+
+/// Moves `uri` to the top of the list, and records it with the desktop's
+/// recent files so other applications see it too.
+pub fn add(uri: &str) {
+    gtk4::RecentManager::default().add_item(uri);
+    let mut items = load();
+    let pinned = match items.iter().position(|item| item.uri == uri) {
+        Some(i) => items.remove(i).pinned,
+        None => false,
+    };
+    let at = if pinned {
+        0
+    } else {
+        items.iter().take_while(|item| item.pinned).count()
+    };
+    items.insert(at, RecentItem { uri: uri.to_string(), pinned });
+    let mut unpinned = 0;
+    items.retain(|item| {
+        if !item.pinned {
+            unpinned += 1;
+        }
+        item.pinned || unpinned <= MAX_RECENT
+    });
+    save(&items);
+}
+
+pub fn remove(uri: &str) {
+    let mut items = load();
+    items.retain(|item| item.uri != uri);
+    save(&items);
+    // Not an error if the desktop never knew about it.
+    let _ = gtk4::RecentManager::default().remove_item(uri);
+}
+
+/// Pinned documents stay at the top of the list until unpinned.
+pub fn set_pinned(uri: &str, pinned: bool) {
+    let mut items = load();
+    let Some(i) = items.iter().position(|item| item.uri == uri) else {
+        return;
+    };
+    let mut item = items.remove(i);
+    item.pinned = pinned;
+    let at = items.iter().take_while(|item| item.pinned).count();
+    items.insert(at, item);
+    save(&items);
+}
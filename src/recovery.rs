@@ -0,0 +1,86 @@
+use std::path::PathBuf;
+
+use gtk4::glib;
+
+use crate::prefs::state_dir;
+
+const GROUP: &str = "Document";
+
+/// Unsaved state of one document, written periodically so it can be restored
+/// after a crash or a session logout.
+pub struct Snapshot {
+    pub id: String,
+    pub uri: Option<String>,
+    pub cursor_line: i32,
+    pub cursor_column: i32,
+    pub text: String,
+}
+
+fn recovery_dir() -> PathBuf {
+    state_dir().join("recovery")
+}
+
+fn snapshot_path(id: &str) -> PathBuf {
+    recovery_dir().join(format!("{id}.snapshot"))
+}
+
+pub fn new_id() -> String {
+    glib::uuid_string_random().to_string()
+}
+
+/// Writes `snapshot` atomically, replacing any earlier one with the same id.
+pub fn write(snapshot: &Snapshot) -> Result<(), String> {
+    std::fs::create_dir_all(recovery_dir()).map_err(|e| e.to_string())?;
+    let key_file = glib::KeyFile::new();
+    if let Some(ref uri) = snapshot.uri {
+        key_file.set_string(GROUP, "uri", uri);
+    }
+    key_file.set_integer(GROUP, "cursor-line", snapshot.cursor_line);
+    key_file.set_integer(GROUP, "cursor-column", snapshot.cursor_column);
+    key_file.set_string(GROUP, "text", &snapshot.text);
+    key_file
+        .save_to_file(snapshot_path(&snapshot.id))
+        .map_err(|e| e.to_string())
+}
+
+pub fn remove(id: &str) {
+    let path = snapshot_path(id);
+    if path.exists()
+        && let Err(e) = std::fs::remove_file(&path)
+    {
+        eprintln!("Failed to remove recovery snapshot {}: {e}", path.display());
+    }
+}
+
+/// Snapshots left behind by a previous run that did not shut down cleanly.
+pub fn load_all() -> Vec<Snapshot> {
+    let Ok(entries) = std::fs::read_dir(recovery_dir()) else {
+        return Vec::new();
+    };
+    let mut snapshots = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "snapshot") {
+            continue;
+        }
+        let Some(id) = path.file_stem().map(|s| s.to_string_lossy().into_owned()) else {
+            continue;
+        };
+        let key_file = glib::KeyFile::new();
+        if let Err(e) = key_file.load_from_file(&path, glib::KeyFileFlags::NONE) {
+            eprintln!("Skipping unreadable recovery snapshot {}: {e}", path.display());
+            continue;
+        }
+        let Ok(text) = key_file.string(GROUP, "text") else {
+            continue;
+        };
+        snapshots.push(Snapshot {
+            id,
+            uri: key_file.string(GROUP, "uri").ok().map(|s| s.to_string()),
+            cursor_line: key_file.integer(GROUP, "cursor-line").unwrap_or(0),
+            cursor_column: key_file.integer(GROUP, "cursor-column").unwrap_or(0),
+            text: text.to_string(),
+        });
+    }
+    snapshots
+}
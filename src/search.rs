@@ -0,0 +1,507 @@
+use std::rc::Rc;
+
+use gtk4::prelude::*;
+use gtk4::{gdk, glib, Orientation, SearchBar, SearchEntry};
+use sourceview5::prelude::*;
+use sourceview5::{Buffer as SourceBuffer, SearchContext, SearchSettings, View as SourceView};
+use webkit6::prelude::*;
+use webkit6::{FindOptions, WebView};
+
+/// Find and replace bar over the editor, backed by a GtkSourceView search
+/// context so every match is highlighted while the bar is open.
+pub struct EditorSearch {
+    bar: SearchBar,
+    entry: SearchEntry,
+    replace_entry: gtk4::Entry,
+    replace_toggle: gtk4::ToggleButton,
+    matches_label: gtk4::Label,
+    view: SourceView,
+    buffer: SourceBuffer,
+    context: SearchContext,
+}
+
+impl EditorSearch {
+    pub fn new(view: &SourceView) -> Rc<Self> {
+        let buffer: SourceBuffer = view.buffer().downcast().unwrap();
+        let settings = SearchSettings::builder().wrap_around(true).build();
+        let context = SearchContext::new(&buffer, Some(&settings));
+        context.set_highlight(false);
+
+        let entry = SearchEntry::builder()
+            .placeholder_text("Find")
+            .hexpand(true)
+            .build();
+        let matches_label = gtk4::Label::builder()
+            .css_classes(["dim-label", "numeric"])
+            .width_chars(10)
+            .build();
+        let previous_button = gtk4::Button::builder()
+            .icon_name("go-up-symbolic")
+            .tooltip_text("Previous Match (Shift+Enter)")
+            .build();
+        let next_button = gtk4::Button::builder()
+            .icon_name("go-down-symbolic")
+            .tooltip_text("Next Match (Enter)")
+            .build();
+
+        let options = gtk4::Box::new(Orientation::Vertical, 6);
+        for (label, property) in [
+            ("Match case", "case-sensitive"),
+            ("Match whole words only", "at-word-boundaries"),
+            ("Regular expressions", "regex-enabled"),
+        ] {
+            let check = gtk4::CheckButton::with_label(label);
+            settings
+                .bind_property(property, &check, "active")
+                .bidirectional()
+                .sync_create()
+                .build();
+            options.append(&check);
+        }
+        let options_button = gtk4::MenuButton::builder()
+            .icon_name("emblem-system-symbolic")
+            .tooltip_text("Search Options")
+            .popover(&gtk4::Popover::builder().child(&options).build())
+            .build();
+        let replace_toggle = gtk4::ToggleButton::builder()
+            .icon_name("edit-find-replace-symbolic")
+            .tooltip_text("Replace (Ctrl+H)")
+            .build();
+
+        let find_row = gtk4::Box::new(Orientation::Horizontal, 6);
+        find_row.append(&entry);
+        find_row.append(&matches_label);
+        find_row.append(&previous_button);
+        find_row.append(&next_button);
+        find_row.append(&options_button);
+        find_row.append(&replace_toggle);
+
+        let replace_entry = gtk4::Entry::builder()
+            .placeholder_text("Replace with")
+            .hexpand(true)
+            .build();
+        let replace_button = gtk4::Button::with_label("Replace");
+        let replace_all_button = gtk4::Button::with_label("Replace All");
+        let replace_row = gtk4::Box::new(Orientation::Horizontal, 6);
+        replace_row.append(&replace_entry);
+        replace_row.append(&replace_button);
+        replace_row.append(&replace_all_button);
+        replace_toggle
+            .bind_property("active", &replace_row, "visible")
+            .sync_create()
+            .build();
+
+        let content = gtk4::Box::new(Orientation::Vertical, 6);
+        content.append(&find_row);
+        content.append(&replace_row);
+        let bar = SearchBar::builder()
+            .child(&content)
+            .show_close_button(true)
+            .build();
+        bar.connect_entry(&entry);
+        bar.bind_property("search-mode-enabled", &context, "highlight")
+            .sync_create()
+            .build();
+
+        let search = Rc::new(Self {
+            bar,
+            entry,
+            replace_entry,
+            replace_toggle,
+            matches_label,
+            view: view.clone(),
+            buffer,
+            context,
+        });
+
+        let weak = Rc::downgrade(&search);
+        search.entry.connect_search_changed(move |entry| {
+            if let Some(search) = weak.upgrade() {
+                search.context.settings().set_search_text(Some(&entry.text()));
+                // Stay on the current match while it still matches.
+                let (start, _) = search.selection();
+                search.select_next(&start);
+            }
+        });
+        let weak = Rc::downgrade(&search);
+        search.entry.connect_activate(move |_| {
+            if let Some(search) = weak.upgrade() {
+                search.next();
+            }
+        });
+        let weak = Rc::downgrade(&search);
+        search.entry.connect_next_match(move |_| {
+            if let Some(search) = weak.upgrade() {
+                search.next();
+            }
+        });
+        let weak = Rc::downgrade(&search);
+        search.entry.connect_previous_match(move |_| {
+            if let Some(search) = weak.upgrade() {
+                search.previous();
+            }
+        });
+        let weak = Rc::downgrade(&search);
+        search.entry.connect_stop_search(move |_| {
+            if let Some(search) = weak.upgrade() {
+                search.bar.set_search_mode(false);
+                search.view.grab_focus();
+            }
+        });
+        // Shift+Enter goes back, like in other GNOME applications.
+        let keys = gtk4::EventControllerKey::new();
+        keys.set_propagation_phase(gtk4::PropagationPhase::Capture);
+        let weak = Rc::downgrade(&search);
+        keys.connect_key_pressed(move |_, key, _, state| {
+            if matches!(key, gdk::Key::Return | gdk::Key::KP_Enter)
+                && state.contains(gdk::ModifierType::SHIFT_MASK)
+                && let Some(search) = weak.upgrade()
+            {
+                search.previous();
+                return glib::Propagation::Stop;
+            }
+            glib::Propagation::Proceed
+        });
+        search.entry.add_controller(keys);
+
+        let weak = Rc::downgrade(&search);
+        previous_button.connect_clicked(move |_| {
+            if let Some(search) = weak.upgrade() {
+                search.previous();
+            }
+        });
+        let weak = Rc::downgrade(&search);
+        next_button.connect_clicked(move |_| {
+            if let Some(search) = weak.upgrade() {
+                search.next();
+            }
+        });
+        let weak = Rc::downgrade(&search);
+        replace_button.connect_clicked(move |_| {
+            if let Some(search) = weak.upgrade() {
+                search.replace();
+            }
+        });
+        let weak = Rc::downgrade(&search);
+        search.replace_entry.connect_activate(move |_| {
+            if let Some(search) = weak.upgrade() {
+                search.replace();
+            }
+        });
+        let weak = Rc::downgrade(&search);
+        replace_all_button.connect_clicked(move |_| {
+            if let Some(search) = weak.upgrade() {
+                search.replace_all();
+            }
+        });
+
+        let weak = Rc::downgrade(&search);
+        search.context.connect_occurrences_count_notify(move |_| {
+            if let Some(search) = weak.upgrade() {
+                search.update_matches();
+            }
+        });
+        let weak = Rc::downgrade(&search);
+        search.context.connect_regex_error_notify(move |_| {
+            if let Some(search) = weak.upgrade() {
+                search.update_matches();
+            }
+        });
+        let weak = Rc::downgrade(&search);
+        search.buffer.connect_mark_set(move |_, _, mark| {
+            if let Some(search) = weak.upgrade()
+                && (mark.name().as_deref() == Some("insert")
+                    || mark.name().as_deref() == Some("selection_bound"))
+            {
+                search.update_matches();
+            }
+        });
+
+        search
+    }
+
+    pub fn widget(&self) -> &SearchBar {
+        &self.bar
+    }
+
+    /// Opens the bar, searching for the selected text if it is on one line.
+    /// With `replace`, the replace row is shown and focused when there is
+    /// already something to search for.
+    pub fn show(&self, replace: bool) {
+        let (start, end) = self.selection();
+        let selected = self.buffer.text(&start, &end, false);
+        if !selected.is_empty() && !selected.contains('\n') {
+            self.entry.set_text(&selected);
+        }
+        self.bar.set_search_mode(true);
+        if replace {
+            self.replace_toggle.set_active(true);
+        }
+        if replace && !self.entry.text().is_empty() {
+            self.replace_entry.grab_focus();
+        } else {
+            self.entry.grab_focus();
+            self.entry.select_region(0, -1);
+        }
+    }
+
+    fn selection(&self) -> (gtk4::TextIter, gtk4::TextIter) {
+        self.buffer.selection_bounds().unwrap_or_else(|| {
+            let cursor = self.buffer.iter_at_mark(&self.buffer.get_insert());
+            (cursor, cursor)
+        })
+    }
+
+    fn select_next(&self, from: &gtk4::TextIter) {
+        if let Some((start, end, _)) = self.context.forward(from) {
+            self.select_match(&start, &end);
+        }
+    }
+
+    fn select_match(&self, start: &gtk4::TextIter, end: &gtk4::TextIter) {
+        self.buffer.select_range(start, end);
+        self.view
+            .scroll_to_mark(&self.buffer.get_insert(), 0.1, false, 0.0, 0.0);
+    }
+
+    fn next(&self) {
+        let (_, end) = self.selection();
+        self.select_next(&end);
+    }
+
+    fn previous(&self) {
+        let (start, _) = self.selection();
+        if let Some((start, end, _)) = self.context.backward(&start) {
+            self.select_match(&start, &end);
+        }
+    }
+
+    /// Replaces the selected match and moves on to the next one. If the
+    /// selection is not a match, only moves to the next one.
+    fn replace(&self) {
+        let (mut start, mut end) = self.selection();
+        if self.context.occurrence_position(&start, &end) > 0 {
+            if let Err(e) = self
+                .context
+                .replace(&mut start, &mut end, &self.replace_entry.text())
+            {
+                self.show_error(&e);
+                return;
+            }
+            self.select_next(&end);
+        } else {
+            self.next();
+        }
+    }
+
+    /// Replaces every match as a single undo step.
+    fn replace_all(&self) {
+        self.buffer.begin_user_action();
+        let result = self.context.replace_all(&self.replace_entry.text());
+        self.buffer.end_user_action();
+        if let Err(e) = result {
+            self.show_error(&e);
+        }
+    }
+
+    fn show_error(&self, error: &glib::Error) {
+        self.matches_label.set_label("Error");
+        self.matches_label.set_tooltip_text(Some(error.message()));
+    }
+
+    /// Shows "n of m" for the selected match, or the total, and flags
+    /// invalid regular expressions on the entry.
+    fn update_matches(&self) {
+        if let Some(error) = self.context.regex_error() {
+            self.entry.add_css_class("error");
+            self.show_error(&error);
+            return;
+        }
+        self.entry.remove_css_class("error");
+        self.matches_label.set_tooltip_text(None);
+        let count = self.context.occurrences_count();
+        let label = if self.entry.text().is_empty() || count < 0 {
+            String::new()
+        } else if count == 0 {
+            "No matches".to_string()
+        } else {
+            let (start, end) = self.selection();
+            match self.context.occurrence_position(&start, &end) {
+                position if position > 0 => format!("{position} of {count}"),
+                _ if count == 1 => "1 match".to_string(),
+                _ => format!("{count} matches"),
+            }
+        };
+        self.matches_label.set_label(&label);
+    }
+}
+
+/// Find bar over the preview, backed by WebKit's find controller. WebKit
+/// highlights every match while a search is active.
+pub struct PreviewSearch {
+    bar: SearchBar,
+    entry: SearchEntry,
+    matches_label: gtk4::Label,
+    webview: WebView,
+}
+
+impl PreviewSearch {
+    pub fn new(webview: &WebView) -> Rc<Self> {
+        let entry = SearchEntry::builder()
+            .placeholder_text("Find in preview")
+            .hexpand(true)
+            .build();
+        let matches_label = gtk4::Label::builder()
+            .css_classes(["dim-label", "numeric"])
+            .width_chars(10)
+            .build();
+        let previous_button = gtk4::Button::builder()
+            .icon_name("go-up-symbolic")
+            .tooltip_text("Previous Match (Shift+Enter)")
+            .build();
+        let next_button = gtk4::Button::builder()
+            .icon_name("go-down-symbolic")
+            .tooltip_text("Next Match (Enter)")
+            .build();
+        let content = gtk4::Box::new(Orientation::Horizontal, 6);
+        content.append(&entry);
+        content.append(&matches_label);
+        content.append(&previous_button);
+        content.append(&next_button);
+        let bar = SearchBar::builder()
+            .child(&content)
+            .show_close_button(true)
+            .build();
+        bar.connect_entry(&entry);
+
+        let search = Rc::new(Self {
+            bar,
+            entry,
+            matches_label,
+            webview: webview.clone(),
+        });
+        let Some(fc) = webview.find_controller() else {
+            return search;
+        };
+
+        let weak = Rc::downgrade(&search);
+        search.entry.connect_search_changed({
+            let fc = fc.clone();
+            move |entry| {
+                if entry.text().is_empty() {
+                    fc.search_finish();
+                    if let Some(search) = weak.upgrade() {
+                        search.matches_label.set_label("");
+                    }
+                } else {
+                    let options = FindOptions::CASE_INSENSITIVE | FindOptions::WRAP_AROUND;
+                    fc.search(&entry.text(), options.bits(), u32::MAX);
+                }
+            }
+        });
+        search.entry.connect_activate({
+            let fc = fc.clone();
+            move |_| fc.search_next()
+        });
+        search.entry.connect_next_match({
+            let fc = fc.clone();
+            move |_| fc.search_next()
+        });
+        search.entry.connect_previous_match({
+            let fc = fc.clone();
+            move |_| fc.search_previous()
+        });
+        next_button.connect_clicked({
+            let fc = fc.clone();
+            move |_| fc.search_next()
+        });
+        previous_button.connect_clicked({
+            let fc = fc.clone();
+            move |_| fc.search_previous()
+        });
+        // Shift+Enter goes back, as in the editor's find bar.
+        let keys = gtk4::EventControllerKey::new();
+        keys.set_propagation_phase(gtk4::PropagationPhase::Capture);
+        keys.connect_key_pressed({
+            let fc = fc.clone();
+            move |_, key, _, state| {
+                if matches!(key, gdk::Key::Return | gdk::Key::KP_Enter)
+                    && state.contains(gdk::ModifierType::SHIFT_MASK)
+                {
+                    fc.search_previous();
+                    return glib::Propagation::Stop;
+                }
+                glib::Propagation::Proceed
+            }
+        });
+        search.entry.add_controller(keys);
+        let weak = Rc::downgrade(&search);
+        search.entry.connect_stop_search({
+            let fc = fc.clone();
+            move |_| {
+                if let Some(search) = weak.upgrade() {
+                    fc.search_finish();
+                    search.bar.set_search_mode(false);
+                    search.webview.grab_focus();
+                }
+            }
+        });
+
+        let weak = Rc::downgrade(&search);
+        fc.connect_found_text(move |_, count| {
+            if let Some(search) = weak.upgrade() {
+                search.set_match_count(count);
+            }
+        });
+        let weak = Rc::downgrade(&search);
+        fc.connect_failed_to_find_text(move |_| {
+            if let Some(search) = weak.upgrade() {
+                search.set_match_count(0);
+            }
+        });
+
+        // `/` starts a search while the preview has focus, like a pager.
+        let preview_keys = gtk4::EventControllerKey::new();
+        preview_keys.set_propagation_phase(gtk4::PropagationPhase::Capture);
+        let weak = Rc::downgrade(&search);
+        preview_keys.connect_key_pressed(move |_, key, _, state| {
+            let modifiers = gdk::ModifierType::CONTROL_MASK | gdk::ModifierType::ALT_MASK;
+            if key == gdk::Key::slash
+                && !state.intersects(modifiers)
+                && let Some(search) = weak.upgrade()
+            {
+                search.show();
+                return glib::Propagation::Stop;
+            }
+            glib::Propagation::Proceed
+        });
+        webview.add_controller(preview_keys);
+
+        search
+    }
+
+    pub fn widget(&self) -> &SearchBar {
+        &self.bar
+    }
+
+    pub fn show(&self) {
+        self.bar.set_search_mode(true);
+        self.entry.grab_focus();
+        self.entry.select_region(0, -1);
+    }
+
+    /// Whether keyboard focus is in the find bar.
+    pub fn has_focus(&self) -> bool {
+        self.bar.is_search_mode() && self.bar.focus_child().is_some()
+    }
+
+    fn set_match_count(&self, count: u32) {
+        let label = match count {
+            _ if self.entry.text().is_empty() => String::new(),
+            0 => "No matches".to_string(),
+            1 => "1 match".to_string(),
+            count => format!("{count} matches"),
+        };
+        self.matches_label.set_label(&label);
+    }
+}
@@ -0,0 +1,114 @@
+use std::path::PathBuf;
+
+use gtk4::glib;
+
+use crate::document::DEFAULT_PANED_POSITION;
+use crate::prefs::state_dir;
+
+const WINDOW_GROUP: &str = "Window";
+const DEFAULT_WIDTH: i32 = 1100;
+const DEFAULT_HEIGHT: i32 = 700;
+
+/// Where the user left off in one document.
+pub struct DocumentState {
+    pub uri: String,
+    pub cursor_line: i32,
+    pub cursor_column: i32,
+    /// First source line visible in the editor.
+    pub top_line: i32,
+    pub paned_position: i32,
+    pub editor_visible: bool,
+    pub viewer_mode: bool,
+}
+
+/// Window layout and open documents, saved when the window closes and
+/// restored on the next launch.
+pub struct Session {
+    pub width: i32,
+    pub height: i32,
+    pub maximized: bool,
+    /// Pane split for documents opened in a new tab.
+    pub paned_position: i32,
+    /// Index into `documents` of the selected tab.
+    pub selected: usize,
+    pub documents: Vec<DocumentState>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+            maximized: false,
+            paned_position: DEFAULT_PANED_POSITION,
+            selected: 0,
+            documents: Vec::new(),
+        }
+    }
+}
+
+fn session_path() -> PathBuf {
+    state_dir().join("session.ini")
+}
+
+/// Reads the last saved session, falling back to defaults for anything
+/// missing or unreadable.
+pub fn load() -> Session {
+    let mut session = Session::default();
+    let key_file = glib::KeyFile::new();
+    if key_file
+        .load_from_file(session_path(), glib::KeyFileFlags::NONE)
+        .is_err()
+    {
+        return session;
+    }
+    let int = |group: &str, key: &str, default: i32| key_file.integer(group, key).unwrap_or(default);
+    let flag = |group: &str, key: &str, default: bool| key_file.boolean(group, key).unwrap_or(default);
+
+    session.width = int(WINDOW_GROUP, "width", DEFAULT_WIDTH).max(360);
+    session.height = int(WINDOW_GROUP, "height", DEFAULT_HEIGHT).max(240);
+    session.maximized = flag(WINDOW_GROUP, "maximized", false);
+    session.paned_position = int(WINDOW_GROUP, "paned-position", DEFAULT_PANED_POSITION);
+    session.selected = int(WINDOW_GROUP, "selected", 0).max(0) as usize;
+    for group in key_file.groups().iter() {
+        if !group.starts_with("Document ") {
+            continue;
+        }
+        let Ok(uri) = key_file.string(group, "uri") else {
+            continue;
+        };
+        session.documents.push(DocumentState {
+            uri: uri.to_string(),
+            cursor_line: int(group, "cursor-line", 0),
+            cursor_column: int(group, "cursor-column", 0),
+            top_line: int(group, "top-line", 0),
+            paned_position: int(group, "paned-position", session.paned_position),
+            editor_visible: flag(group, "editor-visible", true),
+            viewer_mode: flag(group, "viewer-mode", false),
+        });
+    }
+    session
+}
+
+pub fn save(session: &Session) -> Result<(), String> {
+    std::fs::create_dir_all(state_dir()).map_err(|e| e.to_string())?;
+    let key_file = glib::KeyFile::new();
+    key_file.set_integer(WINDOW_GROUP, "width", session.width);
+    key_file.set_integer(WINDOW_GROUP, "height", session.height);
+    key_file.set_boolean(WINDOW_GROUP, "maximized", session.maximized);
+    key_file.set_integer(WINDOW_GROUP, "paned-position", session.paned_position);
+    key_file.set_integer(WINDOW_GROUP, "selected", session.selected as i32);
+    for (i, doc) in session.documents.iter().enumerate() {
+        let group = format!("Document {i}");
+        key_file.set_string(&group, "uri", &doc.uri);
+        key_file.set_integer(&group, "cursor-line", doc.cursor_line);
+        key_file.set_integer(&group, "cursor-column", doc.cursor_column);
+        key_file.set_integer(&group, "top-line", doc.top_line);
+        key_file.set_integer(&group, "paned-position", doc.paned_position);
+        key_file.set_boolean(&group, "editor-visible", doc.editor_visible);
+        key_file.set_boolean(&group, "viewer-mode", doc.viewer_mode);
+    }
+    key_file
+        .save_to_file(session_path())
+        .map_err(|e| e.to_string())
+}
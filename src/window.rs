@@ -0,0 +1,1457 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::Once;
+
+use adw::prelude::*;
+use adw::{
+    Application, ApplicationWindow, ColorScheme, ComboRow, EntryRow, HeaderBar, PreferencesDialog,
+    PreferencesGroup, PreferencesPage, ResponseAppearance, StyleManager, SwitchRow, TabBar,
+    TabPage, TabView, ToastOverlay,
+};
+use gtk4::{gio, glib, Box, Button, MenuButton, Orientation, PropertyExpression, Settings, StringObject};
+
+use crate::document::Document;
+use crate::export::{export_html, pdf_page, pdf_page_setup, print_html, PAPER_SIZES};
+use crate::front_matter::Value;
+use crate::prefs::{PdfTheme, Preferences, Theme, EDITOR_SCHEMES};
+use crate::preview::base_uri_for_preview;
+use crate::recent;
+use crate::recovery::{self, Snapshot};
+use crate::session::{self, DocumentState, Session};
+
+/// How often unsaved buffers are snapshotted for crash recovery.
+const SNAPSHOT_INTERVAL_SECS: u32 = 10;
+
+/// Per-window state: the tab view and the documents hosted in it.
+pub struct MainWindow {
+    window: ApplicationWindow,
+    tab_view: TabView,
+    toast_overlay: ToastOverlay,
+    sidebar_toggle: Button,
+    viewer_mode_action: gio::SimpleAction,
+    /// "Open Recent" submenu, rebuilt whenever the list changes.
+    recent_menu: gio::Menu,
+    documents: RefCell<Vec<Rc<Document>>>,
+    /// Pane split for new tabs when there is no other document to copy it from.
+    paned_position: Cell<i32>,
+}
+
+impl MainWindow {
+    fn document_for_page(&self, page: &TabPage) -> Option<Rc<Document>> {
+        let child = page.child();
+        self.documents
+            .borrow()
+            .iter()
+            .find(|doc| doc.widget().upcast_ref::<gtk4::Widget>() == &child)
+            .cloned()
+    }
+
+    pub fn selected_document(&self) -> Option<Rc<Document>> {
+        self.tab_view
+            .selected_page()
+            .and_then(|page| self.document_for_page(&page))
+    }
+
+    fn page_for_document(&self, doc: &Document) -> TabPage {
+        self.tab_view.page(doc.widget())
+    }
+
+    pub fn add_document(self: &Rc<Self>) -> Rc<Document> {
+        let paned_position = self
+            .selected_document()
+            .map_or(self.paned_position.get(), |doc| doc.paned_position());
+        let doc = Document::new();
+        doc.set_paned_position(paned_position);
+        doc.set_welcome(Some(&build_welcome(self)));
+        self.documents.borrow_mut().push(doc.clone());
+        let page = self.tab_view.append(doc.widget());
+        page.set_title(&doc.title());
+        self.tab_view.set_selected_page(&page);
+
+        let mw = Rc::downgrade(self);
+        let weak_doc = Rc::downgrade(&doc);
+        doc.buffer().connect_modified_changed(move |_| {
+            if let (Some(mw), Some(doc)) = (mw.upgrade(), weak_doc.upgrade()) {
+                mw.update_titles(&doc);
+            }
+        });
+        // The window is titled after the front matter title.
+        let mw = Rc::downgrade(self);
+        let weak_doc = Rc::downgrade(&doc);
+        doc.connect_front_matter_title_changed(move || {
+            if let (Some(mw), Some(doc)) = (mw.upgrade(), weak_doc.upgrade()) {
+                mw.update_titles(&doc);
+            }
+        });
+        doc
+    }
+
+    fn modified_documents(&self) -> Vec<Rc<Document>> {
+        self.documents
+            .borrow()
+            .iter()
+            .filter(|doc| doc.is_modified())
+            .cloned()
+            .collect()
+    }
+
+    /// Opens `file` in a tab, reusing a tab that already shows it or a blank
+    /// untitled one before creating a new tab. `viewer` puts the tab in
+    /// read-only viewer mode. Returns the document, or `None` if the file
+    /// could not be read.
+    pub fn open_file(self: &Rc<Self>, file: gio::File, viewer: bool) -> Option<Rc<Document>> {
+        let doc = self.show_file(file, viewer)?;
+        self.add_recent(&doc);
+        Some(doc)
+    }
+
+    /// `open_file` without recording the file as recently used, for reopening
+    /// a session without reordering the recent files.
+    fn show_file(self: &Rc<Self>, file: gio::File, viewer: bool) -> Option<Rc<Document>> {
+        let existing = self
+            .documents
+            .borrow()
+            .iter()
+            .find(|doc| doc.file().is_some_and(|f| f.equal(&file)))
+            .cloned();
+        let doc = match existing {
+            Some(doc) => doc,
+            None => {
+                let doc = match self.selected_document() {
+                    Some(doc) if doc.is_blank() => doc,
+                    _ => self.add_document(),
+                };
+                if let Err(e) = doc.load(file) {
+                    self.show_error(&format!("Could not open file: {e}"));
+                    return None;
+                }
+                self.update_titles(&doc);
+                doc
+            }
+        };
+        if viewer {
+            doc.set_viewer_mode(true);
+        }
+        self.tab_view.set_selected_page(&self.page_for_document(&doc));
+        self.update_view_controls();
+        Some(doc)
+    }
+
+    /// Reopens the documents of a previous session where they were left.
+    /// Files that no longer exist are skipped.
+    fn restore_session(self: &Rc<Self>, session: &Session) {
+        let mut selected = None;
+        for (i, state) in session.documents.iter().enumerate() {
+            let file = gio::File::for_uri(&state.uri);
+            if !file.query_exists(None::<&gio::Cancellable>) {
+                continue;
+            }
+            let Some(doc) = self.show_file(file, state.viewer_mode) else {
+                continue;
+            };
+            doc.set_paned_position(state.paned_position);
+            doc.set_editor_visible(state.editor_visible);
+            doc.restore_position(state.cursor_line, state.cursor_column, state.top_line);
+            if i == session.selected || selected.is_none() {
+                selected = Some(doc);
+            }
+        }
+        if let Some(doc) = selected {
+            self.tab_view.set_selected_page(&self.page_for_document(&doc));
+        }
+        self.update_view_controls();
+    }
+
+    /// Records the window layout and the documents open in it, in tab order.
+    /// Untitled documents are left to crash recovery.
+    fn save_session(&self) {
+        let (width, height) = self.window.default_size();
+        let selected = self.selected_document();
+        let mut session = Session {
+            width,
+            height,
+            maximized: self.window.is_maximized(),
+            paned_position: selected
+                .as_ref()
+                .map_or(self.paned_position.get(), |doc| doc.paned_position()),
+            ..Session::default()
+        };
+        for i in 0..self.tab_view.n_pages() {
+            let Some(doc) = self.document_for_page(&self.tab_view.nth_page(i)) else {
+                continue;
+            };
+            let Some(file) = doc.file() else {
+                continue;
+            };
+            if selected.as_ref().is_some_and(|s| Rc::ptr_eq(s, &doc)) {
+                session.selected = session.documents.len();
+            }
+            let (cursor_line, cursor_column) = doc.cursor_position();
+            session.documents.push(DocumentState {
+                uri: file.uri().to_string(),
+                cursor_line,
+                cursor_column,
+                top_line: doc.top_line(),
+                paned_position: doc.paned_position(),
+                editor_visible: doc.is_editor_visible(),
+                viewer_mode: doc.is_viewer_mode(),
+            });
+        }
+        if let Err(e) = session::save(&session) {
+            eprintln!("Failed to save session: {e}");
+        }
+    }
+
+    /// Opens a recovered snapshot in a tab, reusing the unmodified tab that
+    /// shows its file or a blank untitled one.
+    fn restore_snapshot(self: &Rc<Self>, snapshot: Snapshot) {
+        let existing = snapshot.uri.as_ref().and_then(|uri| {
+            let file = gio::File::for_uri(uri);
+            self.documents
+                .borrow()
+                .iter()
+                .find(|doc| !doc.is_modified() && doc.file().is_some_and(|f| f.equal(&file)))
+                .cloned()
+        });
+        let doc = match (existing, self.selected_document()) {
+            (Some(doc), _) => doc,
+            (None, Some(doc)) if doc.is_blank() => doc,
+            _ => self.add_document(),
+        };
+        doc.restore(snapshot);
+        self.update_titles(&doc);
+        self.update_view_controls();
+    }
+
+    /// Moves the document's file to the top of the recent documents.
+    fn add_recent(self: &Rc<Self>, doc: &Document) {
+        if let Some(file) = doc.file() {
+            recent::add(&file.uri());
+            self.refresh_recent();
+        }
+    }
+
+    /// Rebuilds the "Open Recent" submenu and the welcome screens.
+    fn refresh_recent(self: &Rc<Self>) {
+        self.recent_menu.remove_all();
+        for item in recent::load() {
+            let file = gio::File::for_uri(&item.uri);
+            // Menu labels treat a single underscore as a mnemonic.
+            let label = display_name(&file).replace('_', "__");
+            let menu_item = gio::MenuItem::new(Some(&label), None);
+            menu_item.set_action_and_target_value(
+                Some("win.open-file"),
+                Some(&(item.uri, false).to_variant()),
+            );
+            self.recent_menu.append_item(&menu_item);
+        }
+        for doc in self.documents.borrow().iter() {
+            doc.set_welcome(Some(&build_welcome(self)));
+        }
+    }
+
+    /// Reports a failure in a toast at the bottom of the window.
+    pub fn show_error(&self, message: &str) {
+        let toast = adw::Toast::builder()
+            .title(message)
+            .use_markup(false)
+            .timeout(0)
+            .build();
+        self.toast_overlay.add_toast(toast);
+    }
+
+    /// Refreshes the tab label and, if `doc` is selected, the window title,
+    /// which prefers the front matter title to the file name. Documents with
+    /// unsaved changes get a "•" prefix.
+    pub fn update_titles(&self, doc: &Document) {
+        let prefix = if doc.is_modified() { "• " } else { "" };
+        self.page_for_document(doc)
+            .set_title(&format!("{prefix}{}", doc.title()));
+        if self
+            .selected_document()
+            .is_some_and(|selected| std::ptr::eq(selected.as_ref(), doc))
+        {
+            let title = doc.front_matter_title().unwrap_or_else(|| doc.title());
+            self.window.set_title(Some(&format!("{prefix}{title} — MarkView")));
+        }
+    }
+
+    /// Syncs the sidebar toggle and viewer mode state with the selected tab.
+    fn update_view_controls(&self) {
+        let doc = self.selected_document();
+        let viewer = doc.as_ref().is_some_and(|doc| doc.is_viewer_mode());
+        self.viewer_mode_action.set_state(&viewer.to_variant());
+        self.sidebar_toggle.set_sensitive(!viewer);
+        let visible = doc.is_none_or(|doc| doc.is_editor_visible());
+        if visible {
+            self.sidebar_toggle.set_icon_name("view-dual-symbolic");
+            self.sidebar_toggle.set_tooltip_text(Some("Hide left panel"));
+        } else {
+            self.sidebar_toggle.set_icon_name("sidebar-show-symbolic");
+            self.sidebar_toggle.set_tooltip_text(Some("Show left panel"));
+        }
+    }
+}
+
+fn display_name(file: &gio::File) -> String {
+    file.basename()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| file.uri().to_string())
+}
+
+/// Welcome screen for blank documents, listing recent documents with buttons
+/// to pin them to the top or remove them from the list.
+fn build_welcome(mw: &Rc<MainWindow>) -> gtk4::Widget {
+    let items = recent::load();
+    let page = adw::StatusPage::builder()
+        .icon_name("io.github.v8v88v8v88.MarkView")
+        .title("MarkView")
+        .description(if items.is_empty() {
+            "Start typing Markdown on the left, or open a file."
+        } else {
+            "Start typing Markdown on the left, or pick up a recent document."
+        })
+        .build();
+    if items.is_empty() {
+        return page.upcast();
+    }
+
+    let group = PreferencesGroup::new();
+    group.set_title("Recent Documents");
+    for item in items {
+        let file = gio::File::for_uri(&item.uri);
+        let location = file
+            .parent()
+            .map(|p| p.parse_name().to_string())
+            .unwrap_or_default();
+        let row = adw::ActionRow::builder()
+            .title(display_name(&file))
+            .subtitle(location)
+            .use_markup(false)
+            .activatable(true)
+            .action_name("win.open-file")
+            .action_target(&(item.uri.clone(), false).to_variant())
+            .build();
+
+        let pin_button = gtk4::ToggleButton::builder()
+            .icon_name("view-pin-symbolic")
+            .tooltip_text(if item.pinned { "Unpin" } else { "Pin" })
+            .active(item.pinned)
+            .valign(gtk4::Align::Center)
+            .css_classes(["flat"])
+            .build();
+        pin_button.connect_toggled({
+            let mw = Rc::downgrade(mw);
+            let uri = item.uri.clone();
+            move |button| {
+                recent::set_pinned(&uri, button.is_active());
+                if let Some(mw) = mw.upgrade() {
+                    mw.refresh_recent();
+                }
+            }
+        });
+        let remove_button = Button::builder()
+            .icon_name("edit-delete-symbolic")
+            .tooltip_text("Remove from Recent Documents")
+            .valign(gtk4::Align::Center)
+            .css_classes(["flat"])
+            .build();
+        remove_button.connect_clicked({
+            let mw = Rc::downgrade(mw);
+            let uri = item.uri;
+            move |_| {
+                recent::remove(&uri);
+                if let Some(mw) = mw.upgrade() {
+                    mw.refresh_recent();
+                }
+            }
+        });
+        row.add_suffix(&pin_button);
+        row.add_suffix(&remove_button);
+        group.add(&row);
+    }
+    let clamp = adw::Clamp::builder()
+        .maximum_size(480)
+        .child(&group)
+        .build();
+    page.set_child(Some(&clamp));
+    page.upcast()
+}
+
+fn create_md_filters() -> gio::ListStore {
+    let md = gtk4::FileFilter::new();
+    md.add_pattern("*.md");
+    md.add_pattern("*.markdown");
+    md.set_name(Some("Markdown Files"));
+    let all = gtk4::FileFilter::new();
+    all.add_pattern("*");
+    all.set_name(Some("All Files"));
+    let filters = gio::ListStore::new::<gtk4::FileFilter>();
+    filters.append(&md);
+    filters.append(&all);
+    filters
+}
+
+fn create_html_filters() -> gio::ListStore {
+    let html = gtk4::FileFilter::new();
+    html.add_mime_type("text/html");
+    html.add_pattern("*.html");
+    html.set_name(Some("HTML"));
+    let all = gtk4::FileFilter::new();
+    all.add_pattern("*");
+    all.set_name(Some("All Files"));
+    let filters = gio::ListStore::new::<gtk4::FileFilter>();
+    filters.append(&html);
+    filters.append(&all);
+    filters
+}
+
+/// Suggested name for exporting `doc`: its file name with `extension`, or
+/// "untitled" for a document that was never saved.
+fn export_file_name(doc: &Document, extension: &str) -> String {
+    let stem = doc
+        .file()
+        .and_then(|f| f.basename())
+        .and_then(|name| {
+            std::path::Path::new(&name)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| "untitled".to_string());
+    format!("{stem}.{extension}")
+}
+
+/// Asks for the HTML export options, then for the file to write.
+fn export_html_document(mw: &Rc<MainWindow>, doc: &Rc<Document>) {
+    let theme_row = ComboRow::builder()
+        .title("Theme")
+        .model(&gtk4::StringList::new(&["Light", "Dark"]))
+        .selected(u32::from(StyleManager::default().is_dark()))
+        .build();
+    let images_row = SwitchRow::builder()
+        .title("Embed images")
+        .subtitle("Include local images in the file")
+        .active(true)
+        .build();
+    let group = PreferencesGroup::new();
+    group.add(&theme_row);
+    group.add(&images_row);
+
+    let dialog = adw::AlertDialog::new(Some("Export as HTML"), None);
+    dialog.set_extra_child(Some(&group));
+    dialog.add_responses(&[("cancel", "_Cancel"), ("export", "_Export…")]);
+    dialog.set_response_appearance("export", ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("export"));
+    dialog.set_close_response("cancel");
+    let mw = mw.clone();
+    let doc = doc.clone();
+    let parent = mw.window.clone();
+    dialog.choose(Some(&parent), None::<&gio::Cancellable>, move |response| {
+        if response != "export" {
+            return;
+        }
+        let dark = theme_row.selected() == 1;
+        let inline_images = images_row.is_active();
+        let file_dialog = gtk4::FileDialog::builder()
+            .title("Export as HTML")
+            .initial_name(export_file_name(&doc, "html"))
+            .build();
+        file_dialog.set_filters(Some(&create_html_filters()));
+        let parent = mw.window.clone();
+        file_dialog.save(Some(&parent), None::<&gio::Cancellable>, move |result| {
+            let Ok(file) = result else {
+                return;
+            };
+            let base_uri = base_uri_for_preview(doc.file().as_ref());
+            let html = export_html(&doc.text(), base_uri.as_deref(), dark, inline_images);
+            if let Err(e) = file.replace_contents(
+                html.as_bytes(),
+                None,
+                false,
+                gio::FileCreateFlags::REPLACE_DESTINATION,
+                None::<&gio::Cancellable>,
+            ) {
+                mw.show_error(&format!("Could not export “{}”: {}", display_name(&file), e.message()));
+            }
+        });
+    });
+}
+
+/// Asks for the page setup and theme of a PDF export, remembered for next
+/// time and for `markview --export`, then for the file to write.
+fn export_pdf_document(mw: &Rc<MainWindow>, doc: &Rc<Document>) {
+    let prefs = Preferences::default();
+    let paper_names: Vec<String> = PAPER_SIZES.iter().map(|(name, _)| name.to_string()).collect();
+    let paper_labels: Vec<&str> = PAPER_SIZES.iter().map(|(_, label)| *label).collect();
+    let paper_row = ComboRow::builder()
+        .title("Paper size")
+        .model(&gtk4::StringList::new(&paper_labels))
+        .build();
+    let names = paper_names.clone();
+    prefs
+        .bind_property("pdf-paper", &paper_row, "selected")
+        .transform_to(move |_, paper: String| {
+            let paper = if paper.is_empty() {
+                gtk4::PaperSize::default().to_string()
+            } else {
+                paper
+            };
+            Some(names.iter().position(|name| *name == paper).unwrap_or(0) as u32)
+        })
+        .transform_from(move |_, selected: u32| paper_names.get(selected as usize).cloned())
+        .bidirectional()
+        .sync_create()
+        .build();
+    let orientation_row = ComboRow::builder()
+        .title("Orientation")
+        .model(&gtk4::StringList::new(&["Portrait", "Landscape"]))
+        .build();
+    prefs
+        .bind_property("pdf-landscape", &orientation_row, "selected")
+        .transform_to(|_, landscape: bool| Some(u32::from(landscape)))
+        .transform_from(|_, selected: u32| Some(selected == 1))
+        .bidirectional()
+        .sync_create()
+        .build();
+    let margin_row = adw::SpinRow::with_range(0.0, 50.0, 1.0);
+    margin_row.set_title("Margins");
+    margin_row.set_subtitle("Millimetres on every side");
+    prefs
+        .bind_property("pdf-margin", &margin_row, "value")
+        .bidirectional()
+        .sync_create()
+        .build();
+    let theme_row = ComboRow::builder()
+        .title("Theme")
+        .model(&gtk4::StringList::new(&["Light", "Dark", "Print"]))
+        .build();
+    prefs
+        .bind_property("pdf-theme", &theme_row, "selected")
+        .transform_to(|_, theme: PdfTheme| Some(theme as u32))
+        .transform_from(|_, selected: u32| {
+            Some(match selected {
+                1 => PdfTheme::Dark,
+                2 => PdfTheme::Print,
+                _ => PdfTheme::Light,
+            })
+        })
+        .bidirectional()
+        .sync_create()
+        .build();
+    let header_footer_row = SwitchRow::builder()
+        .title("Header and footer")
+        .subtitle("Title above and page numbers below every page")
+        .build();
+    prefs
+        .bind_property("pdf-header-footer", &header_footer_row, "active")
+        .bidirectional()
+        .sync_create()
+        .build();
+    let group = PreferencesGroup::new();
+    group.add(&paper_row);
+    group.add(&orientation_row);
+    group.add(&margin_row);
+    group.add(&theme_row);
+    group.add(&header_footer_row);
+
+    let dialog = adw::AlertDialog::new(Some("Export as PDF"), None);
+    dialog.set_extra_child(Some(&group));
+    dialog.add_responses(&[("cancel", "_Cancel"), ("export", "_Export…")]);
+    dialog.set_response_appearance("export", ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("export"));
+    dialog.set_close_response("cancel");
+    let mw = mw.clone();
+    let doc = doc.clone();
+    let parent = mw.window.clone();
+    dialog.choose(Some(&parent), None::<&gio::Cancellable>, move |response| {
+        if response != "export" {
+            return;
+        }
+        let file_dialog = gtk4::FileDialog::builder()
+            .title("Export as PDF")
+            .initial_name(export_file_name(&doc, "pdf"))
+            .build();
+        file_dialog.set_filters(Some(&create_pdf_filters()));
+        let parent = mw.window.clone();
+        file_dialog.save(Some(&parent), None::<&gio::Cancellable>, move |result| {
+            let Ok(file) = result else {
+                return;
+            };
+            let page_setup = pdf_page_setup();
+            let page = pdf_page(&doc.text(), &doc.title(), &page_setup);
+            let base_uri = base_uri_for_preview(doc.file().as_ref());
+            let name = display_name(&file);
+            print_html(&page, base_uri.as_deref(), &file, &page_setup, move |result| {
+                match result {
+                    Ok(()) => mw.toast_overlay.add_toast(adw::Toast::new(&format!("Exported “{name}”"))),
+                    Err(e) => mw.show_error(&format!("Could not export “{name}”: {e}")),
+                }
+            });
+        });
+    });
+}
+
+/// Edits the common front matter fields of `doc` in a dialog. Tags are typed
+/// comma-separated; fields left empty are removed. Only fields the user
+/// changed are written back, so untouched ones keep their formatting.
+fn edit_properties(mw: &Rc<MainWindow>, doc: &Rc<Document>) {
+    if doc.is_viewer_mode() {
+        mw.toast_overlay
+            .add_toast(adw::Toast::new("Leave viewer mode to edit the properties"));
+        return;
+    }
+    let front_matter = doc.front_matter();
+    // Existing keys keep their spelling; tags may be called keywords.
+    let key = |name: &str| {
+        front_matter
+            .as_ref()
+            .and_then(|front_matter| front_matter.field(name))
+            .map_or_else(|| name.to_string(), |field| field.key.clone())
+    };
+    let tags_key = if front_matter.as_ref().is_some_and(|f| f.field("tags").is_none() && f.field("keywords").is_some()) {
+        key("keywords")
+    } else {
+        key("tags")
+    };
+    let text_fields: Vec<(String, EntryRow, glib::GString)> = ["Title", "Author", "Date", "Description"]
+        .iter()
+        .map(|label| {
+            let row = EntryRow::builder().title(*label).build();
+            let key = key(&label.to_lowercase());
+            if let Some(text) = front_matter.as_ref().and_then(|f| f.text(&key)) {
+                row.set_text(&text);
+            }
+            if front_matter.as_ref().is_some_and(|f| f.is_nested(&key)) {
+                set_nested(&row);
+            }
+            let initial = row.text();
+            (key, row, initial)
+        })
+        .collect();
+    let tags_row = EntryRow::builder().title("Tags, separated by commas").build();
+    if let Some(front_matter) = &front_matter {
+        tags_row.set_text(&front_matter.list(&tags_key).join(", "));
+        if front_matter.is_nested(&tags_key) {
+            set_nested(&tags_row);
+        }
+    }
+    let initial_tags = tags_row.text();
+    let group = PreferencesGroup::new();
+    for (_, row, _) in &text_fields {
+        group.add(row);
+    }
+    group.add(&tags_row);
+
+    let dialog = adw::AlertDialog::new(Some("Document Properties"), None);
+    dialog.set_extra_child(Some(&group));
+    dialog.add_responses(&[("cancel", "_Cancel"), ("apply", "_Apply")]);
+    dialog.set_response_appearance("apply", ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("apply"));
+    dialog.set_close_response("cancel");
+    let doc = doc.clone();
+    dialog.choose(Some(&mw.window), None::<&gio::Cancellable>, move |response| {
+        if response != "apply" {
+            return;
+        }
+        let mut changes: Vec<(String, Value)> = text_fields
+            .iter()
+            .filter(|(_, row, initial)| row.text() != *initial)
+            .map(|(key, row, _)| (key.clone(), Value::Text(row.text().trim().to_string())))
+            .collect();
+        if tags_row.text() != initial_tags {
+            let tags = tags_row
+                .text()
+                .split(',')
+                .map(|tag| tag.trim().to_string())
+                .filter(|tag| !tag.is_empty())
+                .collect();
+            changes.push((tags_key, Value::List(tags)));
+        }
+        if !changes.is_empty() {
+            doc.set_front_matter(&changes);
+        }
+    });
+}
+
+/// Makes the row of a property holding nested values read-only; the dialog
+/// only edits plain text and lists.
+fn set_nested(row: &EntryRow) {
+    row.set_editable(false);
+    row.set_tooltip_text(Some("Holds nested values, which can only be edited in the document"));
+}
+
+fn create_pdf_filters() -> gio::ListStore {
+    let pdf = gtk4::FileFilter::new();
+    pdf.add_mime_type("application/pdf");
+    pdf.add_pattern("*.pdf");
+    pdf.set_name(Some("PDF"));
+    let all = gtk4::FileFilter::new();
+    all.add_pattern("*");
+    all.set_name(Some("All Files"));
+    let filters = gio::ListStore::new::<gtk4::FileFilter>();
+    filters.append(&pdf);
+    filters.append(&all);
+    filters
+}
+
+/// Saves `doc` to its file, asking for one first if it is untitled.
+/// `on_done` receives whether the document ended up on disk.
+fn save_document(mw: &Rc<MainWindow>, doc: &Rc<Document>, on_done: impl FnOnce(bool) + 'static) {
+    let Some(file) = doc.file() else {
+        save_document_as(mw, doc, on_done);
+        return;
+    };
+    let backup = Preferences::default().backup();
+    match doc.save_to(&file, true, backup) {
+        Ok(()) => on_done(true),
+        Err(e) if e.matches(gio::IOErrorEnum::WrongEtag) => {
+            confirm_overwrite(mw, doc, on_done);
+        }
+        Err(e) => {
+            mw.show_error(&format!("Could not save “{}”: {}", doc.title(), e.message()));
+            on_done(false);
+        }
+    }
+}
+
+/// The file changed on disk since it was opened; let the user decide whether
+/// their version wins.
+fn confirm_overwrite(mw: &Rc<MainWindow>, doc: &Rc<Document>, on_done: impl FnOnce(bool) + 'static) {
+    let dialog = adw::AlertDialog::new(
+        Some("File Changed on Disk"),
+        Some(&format!(
+            "“{}” was modified by another program after it was opened. Saving will replace those changes.",
+            doc.title()
+        )),
+    );
+    dialog.add_responses(&[("cancel", "_Cancel"), ("overwrite", "_Overwrite")]);
+    dialog.set_response_appearance("overwrite", ResponseAppearance::Destructive);
+    dialog.set_default_response(Some("cancel"));
+    dialog.set_close_response("cancel");
+    let mw = mw.clone();
+    let doc = doc.clone();
+    let parent = mw.window.clone();
+    dialog.choose(Some(&parent), None::<&gio::Cancellable>, move |response| {
+        if response != "overwrite" {
+            on_done(false);
+            return;
+        }
+        let Some(file) = doc.file() else {
+            on_done(false);
+            return;
+        };
+        let backup = Preferences::default().backup();
+        match doc.save_to(&file, false, backup) {
+            Ok(()) => on_done(true),
+            Err(e) => {
+                mw.show_error(&format!("Could not save “{}”: {}", doc.title(), e.message()));
+                on_done(false);
+            }
+        }
+    });
+}
+
+fn save_document_as(mw: &Rc<MainWindow>, doc: &Rc<Document>, on_done: impl FnOnce(bool) + 'static) {
+    let dialog = if let Some(ref f) = doc.file() {
+        gtk4::FileDialog::builder()
+            .title("Save Markdown File")
+            .initial_file(f)
+            .build()
+    } else {
+        gtk4::FileDialog::builder()
+            .title("Save Markdown File")
+            .initial_name("untitled.md")
+            .build()
+    };
+    dialog.set_filters(Some(&create_md_filters()));
+    let mw = mw.clone();
+    let doc = doc.clone();
+    let parent = mw.window.clone();
+    dialog.save(Some(&parent), None::<&gio::Cancellable>, move |result| {
+        let Ok(file) = result else {
+            on_done(false);
+            return;
+        };
+        // The file chooser already asked before replacing an existing file.
+        let backup = Preferences::default().backup();
+        match doc.save_to(&file, false, backup) {
+            Ok(()) => {
+                doc.set_file(Some(file));
+                mw.update_titles(&doc);
+                mw.add_recent(&doc);
+                on_done(true);
+            }
+            Err(e) => {
+                let name = display_name(&file);
+                mw.show_error(&format!("Could not save “{}”: {}", name, e.message()));
+                on_done(false);
+            }
+        }
+    });
+}
+
+/// Offers to restore documents left unsaved by a previous run that did not
+/// shut down cleanly.
+fn offer_recovery(mw: &Rc<MainWindow>) {
+    let snapshots = recovery::load_all();
+    if snapshots.is_empty() {
+        return;
+    }
+    let body = match snapshots.len() {
+        1 => "MarkView did not shut down cleanly. One document with unsaved changes can be restored.".to_string(),
+        n => format!("MarkView did not shut down cleanly. {n} documents with unsaved changes can be restored."),
+    };
+    let dialog = adw::AlertDialog::new(Some("Recover Unsaved Documents?"), Some(&body));
+    dialog.add_responses(&[("discard", "_Discard"), ("recover", "_Recover")]);
+    dialog.set_response_appearance("discard", ResponseAppearance::Destructive);
+    dialog.set_response_appearance("recover", ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("recover"));
+    // Dismissing the dialog keeps the snapshots for the next launch.
+    dialog.set_close_response("later");
+    let mw = mw.clone();
+    let parent = mw.window.clone();
+    dialog.choose(Some(&parent), None::<&gio::Cancellable>, move |response| {
+        match response.as_str() {
+            "recover" => {
+                for snapshot in snapshots {
+                    mw.restore_snapshot(snapshot);
+                }
+            }
+            "discard" => {
+                for snapshot in &snapshots {
+                    recovery::remove(&snapshot.id);
+                }
+            }
+            _ => {}
+        }
+    });
+}
+
+/// Saves each document in turn, stopping at the first one that is not saved.
+fn save_documents(
+    mw: &Rc<MainWindow>,
+    mut docs: Vec<Rc<Document>>,
+    on_done: std::boxed::Box<dyn FnOnce(bool)>,
+) {
+    if docs.is_empty() {
+        on_done(true);
+        return;
+    }
+    let doc = docs.remove(0);
+    // Bring the tab forward so the user knows which document a Save As dialog is for.
+    mw.tab_view.set_selected_page(&mw.page_for_document(&doc));
+    let next = mw.clone();
+    save_document(mw, &doc, move |saved| {
+        if saved {
+            save_documents(&next, docs, on_done);
+        } else {
+            on_done(false);
+        }
+    });
+}
+
+/// Asks whether to save, discard or keep `docs` before they are closed.
+/// `on_response` receives `true` once it is safe to proceed: the documents were
+/// saved or the user chose to discard them.
+fn confirm_close(
+    mw: &Rc<MainWindow>,
+    docs: Vec<Rc<Document>>,
+    on_response: impl FnOnce(bool) + 'static,
+) {
+    let body = match docs.as_slice() {
+        [doc] => format!(
+            "“{}” has unsaved changes. Changes which are not saved will be permanently lost.",
+            doc.title()
+        ),
+        _ => format!(
+            "{} documents have unsaved changes. Changes which are not saved will be permanently lost.",
+            docs.len()
+        ),
+    };
+    let dialog = adw::AlertDialog::new(Some("Save Changes?"), Some(&body));
+    dialog.add_responses(&[
+        ("cancel", "_Cancel"),
+        ("discard", "_Discard"),
+        ("save", "_Save"),
+    ]);
+    dialog.set_response_appearance("discard", ResponseAppearance::Destructive);
+    dialog.set_response_appearance("save", ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("save"));
+    dialog.set_close_response("cancel");
+    let mw = mw.clone();
+    let parent = mw.window.clone();
+    dialog.choose(Some(&parent), None::<&gio::Cancellable>, move |response| {
+        match response.as_str() {
+            "save" => save_documents(&mw, docs, std::boxed::Box::new(on_response)),
+            "discard" => on_response(true),
+            _ => on_response(false),
+        }
+    });
+}
+
+pub fn build_ui(app: &Application, files: &[gio::File], viewer: bool) {
+    let settings = Settings::default().expect("Failed to get default settings");
+    settings.set_gtk_keynav_use_caret(false);
+    settings.set_gtk_error_bell(false);
+
+    let session = session::load();
+
+    Preferences::default()
+        .bind_property("theme", &StyleManager::default(), "color-scheme")
+        .transform_to(|_, theme: Theme| Some(ColorScheme::from(theme)))
+        .sync_create()
+        .build();
+
+    // --- Header Bar ---
+    let header_bar = HeaderBar::new();
+
+    let open_button = Button::builder()
+        .icon_name("document-open-symbolic")
+        .tooltip_text("Open (Ctrl+O)")
+        .action_name("win.open")
+        .build();
+
+    let new_tab_button = Button::builder()
+        .icon_name("tab-new-symbolic")
+        .tooltip_text("New Tab (Ctrl+T)")
+        .action_name("win.new-tab")
+        .build();
+
+    let save_button = Button::builder()
+        .icon_name("media-floppy-symbolic")
+        .tooltip_text("Save (Ctrl+S)")
+        .action_name("win.save")
+        .build();
+
+    let export_pdf_button = Button::builder()
+        .icon_name("document-save-symbolic")
+        .tooltip_text("Export as PDF")
+        .action_name("win.export-pdf")
+        .build();
+
+    let menu_button = MenuButton::builder()
+        .icon_name("open-menu-symbolic")
+        .build();
+
+    let sidebar_toggle = Button::builder()
+        .icon_name("view-dual-symbolic")
+        .tooltip_text("Hide left panel")
+        .build();
+
+    header_bar.pack_start(&open_button);
+    header_bar.pack_start(&new_tab_button);
+    header_bar.pack_start(&sidebar_toggle);
+    // pack_end adds right-to-left, so menu first, then pdf, then save
+    header_bar.pack_end(&menu_button);
+    header_bar.pack_end(&export_pdf_button);
+    header_bar.pack_end(&save_button);
+
+    // --- Tabs ---
+    let tab_view = TabView::builder().vexpand(true).hexpand(true).build();
+    let tab_bar = TabBar::builder().view(&tab_view).autohide(true).build();
+
+    // --- Window ---
+    let content = Box::new(Orientation::Vertical, 0);
+    content.append(&header_bar);
+    content.append(&tab_bar);
+    content.append(&tab_view);
+
+    let toast_overlay = ToastOverlay::new();
+    toast_overlay.set_child(Some(&content));
+
+    let window = ApplicationWindow::builder()
+        .application(app)
+        .title("MarkView")
+        .default_width(session.width)
+        .default_height(session.height)
+        .maximized(session.maximized)
+        .content(&toast_overlay)
+        .build();
+
+    let viewer_mode_action =
+        gio::SimpleAction::new_stateful("viewer-mode", None, &false.to_variant());
+
+    let mw = Rc::new(MainWindow {
+        window: window.clone(),
+        tab_view: tab_view.clone(),
+        toast_overlay: toast_overlay.clone(),
+        sidebar_toggle: sidebar_toggle.clone(),
+        viewer_mode_action: viewer_mode_action.clone(),
+        recent_menu: gio::Menu::new(),
+        documents: RefCell::new(Vec::new()),
+        paned_position: Cell::new(session.paned_position),
+    });
+
+    {
+        let mw = mw.clone();
+        sidebar_toggle.connect_clicked(move |_| {
+            if let Some(doc) = mw.selected_document() {
+                doc.set_editor_visible(!doc.is_editor_visible());
+            }
+            mw.update_view_controls();
+        });
+    }
+
+    {
+        let mw = mw.clone();
+        tab_view.connect_selected_page_notify(move |_| {
+            if let Some(doc) = mw.selected_document() {
+                mw.update_titles(&doc);
+                doc.grab_focus();
+            }
+            mw.update_view_controls();
+        });
+    }
+
+    {
+        let mw = mw.clone();
+        tab_view.connect_close_page(move |view, page| {
+            let Some(doc) = mw.document_for_page(page) else {
+                view.close_page_finish(page, true);
+                return glib::Propagation::Stop;
+            };
+            let close = {
+                let mw = mw.clone();
+                let doc = doc.clone();
+                let page = page.clone();
+                move |confirmed: bool| {
+                    if confirmed {
+                        doc.discard_snapshot();
+                        mw.documents
+                            .borrow_mut()
+                            .retain(|d| !Rc::ptr_eq(d, &doc));
+                    }
+                    mw.tab_view.close_page_finish(&page, confirmed);
+                    // Keep at least one document around so the actions always have a target.
+                    if mw.tab_view.n_pages() == 0 {
+                        mw.add_document();
+                    }
+                }
+            };
+            if doc.is_modified() {
+                confirm_close(&mw, vec![doc], close);
+            } else {
+                close(true);
+            }
+            glib::Propagation::Stop
+        });
+    }
+
+    {
+        let mw = mw.clone();
+        window.connect_close_request(move |window| {
+            let modified = mw.modified_documents();
+            if modified.is_empty() {
+                mw.save_session();
+                return glib::Propagation::Proceed;
+            }
+            let close = {
+                let mw = mw.clone();
+                let window = window.clone();
+                let docs = modified.clone();
+                move |confirmed: bool| {
+                    if confirmed {
+                        for doc in &docs {
+                            doc.discard_snapshot();
+                        }
+                        mw.save_session();
+                        window.destroy();
+                    }
+                }
+            };
+            confirm_close(&mw, modified, close);
+            glib::Propagation::Stop
+        });
+    }
+
+    // Snapshot unsaved buffers so a crash or logout does not lose them.
+    let snapshot_source = glib::timeout_add_seconds_local(SNAPSHOT_INTERVAL_SECS, {
+        let mw = Rc::downgrade(&mw);
+        move || {
+            if let Some(mw) = mw.upgrade() {
+                for doc in mw.documents.borrow().iter() {
+                    doc.write_snapshot();
+                }
+            }
+            glib::ControlFlow::Continue
+        }
+    });
+
+    // Re-render every preview when the light/dark appearance flips.
+    let dark_handler = StyleManager::default().connect_dark_notify({
+        let mw = Rc::downgrade(&mw);
+        move |_| {
+            if let Some(mw) = mw.upgrade() {
+                for doc in mw.documents.borrow().iter() {
+                    doc.refresh_theme();
+                }
+            }
+        }
+    });
+    let dark_handler = RefCell::new(Some(dark_handler));
+    let snapshot_source = RefCell::new(Some(snapshot_source));
+    window.connect_destroy(move |_| {
+        if let Some(id) = dark_handler.take() {
+            StyleManager::default().disconnect(id);
+        }
+        if let Some(id) = snapshot_source.take() {
+            id.remove();
+        }
+    });
+
+    // --- Menu ---
+    let menu = gio::Menu::new();
+    let file_sec = gio::Menu::new();
+    file_sec.append(Some("New Tab"), Some("win.new-tab"));
+    file_sec.append(Some("Open…"), Some("win.open"));
+    file_sec.append_submenu(Some("Open Recent"), &mw.recent_menu);
+    file_sec.append(Some("Save As…"), Some("win.save-as"));
+    file_sec.append(Some("Properties…"), Some("win.properties"));
+    file_sec.append(Some("Export as HTML…"), Some("win.export-html"));
+    file_sec.append(Some("Export as PDF…"), Some("win.export-pdf"));
+    file_sec.append(Some("Close Tab"), Some("win.close-tab"));
+    menu.append_section(None, &file_sec);
+    let view_sec = gio::Menu::new();
+    view_sec.append(Some("Outline"), Some("win.show-outline"));
+    view_sec.append(Some("Viewer Mode"), Some("win.viewer-mode"));
+    menu.append_section(None, &view_sec);
+    let app_sec = gio::Menu::new();
+    app_sec.append(Some("Preferences"), Some("win.preferences"));
+    app_sec.append(Some("Keyboard Shortcuts"), Some("app.shortcuts"));
+    app_sec.append(Some("About"), Some("app.about"));
+    app_sec.append(Some("Quit"), Some("app.quit"));
+    menu.append_section(None, &app_sec);
+    menu_button.set_menu_model(Some(&menu));
+    mw.refresh_recent();
+
+    // === Actions ===
+
+    // New Tab
+    let new_tab_action = gio::SimpleAction::new("new-tab", None);
+    {
+        let mw = mw.clone();
+        new_tab_action.connect_activate(move |_, _| {
+            mw.add_document();
+        });
+    }
+    window.add_action(&new_tab_action);
+
+    // Close Tab
+    let close_tab_action = gio::SimpleAction::new("close-tab", None);
+    {
+        let mw = mw.clone();
+        close_tab_action.connect_activate(move |_, _| {
+            if let Some(page) = mw.tab_view.selected_page() {
+                mw.tab_view.close_page(&page);
+            }
+        });
+    }
+    window.add_action(&close_tab_action);
+
+    // Open a file by URI, optionally in viewer mode; used when the
+    // application is asked to open files.
+    let open_file_action =
+        gio::SimpleAction::new("open-file", Some(&<(String, bool)>::static_variant_type()));
+    {
+        let mw = mw.clone();
+        open_file_action.connect_activate(move |_, param| {
+            if let Some((uri, viewer)) = param.and_then(|p| p.get::<(String, bool)>()) {
+                mw.open_file(gio::File::for_uri(&uri), viewer);
+            }
+        });
+    }
+    window.add_action(&open_file_action);
+
+    // Find, in the editor or the preview depending on focus
+    let find_action = gio::SimpleAction::new("find", None);
+    {
+        let mw = mw.clone();
+        find_action.connect_activate(move |_, _| {
+            if let Some(doc) = mw.selected_document() {
+                doc.show_search();
+            }
+        });
+    }
+    window.add_action(&find_action);
+
+    // Find and Replace, always in the editor
+    let replace_action = gio::SimpleAction::new("replace", None);
+    {
+        let mw = mw.clone();
+        replace_action.connect_activate(move |_, _| {
+            if let Some(doc) = mw.selected_document() {
+                doc.show_editor_search(true);
+                mw.update_view_controls();
+            }
+        });
+    }
+    window.add_action(&replace_action);
+
+    // Outline: toggles the preference every document's panel follows.
+    window.add_action(&gio::PropertyAction::new("show-outline", &Preferences::default(), "show-outline"));
+
+    // Viewer Mode
+    {
+        let mw = mw.clone();
+        viewer_mode_action.connect_change_state(move |action, value| {
+            let Some(enabled) = value.and_then(|v| v.get::<bool>()) else {
+                return;
+            };
+            if let Some(doc) = mw.selected_document() {
+                doc.set_viewer_mode(enabled);
+            }
+            action.set_state(&enabled.to_variant());
+            mw.update_view_controls();
+        });
+    }
+    window.add_action(&viewer_mode_action);
+
+    // Open
+    let open_action = gio::SimpleAction::new("open", None);
+    {
+        let mw = mw.clone();
+        open_action.connect_activate(move |_, _| {
+            let dialog = gtk4::FileDialog::builder()
+                .title("Open Markdown File")
+                .build();
+            dialog.set_filters(Some(&create_md_filters()));
+            let mw = mw.clone();
+            let parent = mw.window.clone();
+            dialog.open_multiple(Some(&parent), None::<&gio::Cancellable>, move |result| {
+                if let Ok(files) = result {
+                    for file in files.iter::<gio::File>().flatten() {
+                        mw.open_file(file, false);
+                    }
+                }
+            });
+        });
+    }
+    window.add_action(&open_action);
+
+    // Save
+    let save_action = gio::SimpleAction::new("save", None);
+    {
+        let mw = mw.clone();
+        save_action.connect_activate(move |_, _| {
+            let Some(doc) = mw.selected_document() else {
+                return;
+            };
+            save_document(&mw, &doc, |_| {});
+        });
+    }
+    window.add_action(&save_action);
+
+    // Save As
+    let save_as_action = gio::SimpleAction::new("save-as", None);
+    {
+        let mw = mw.clone();
+        save_as_action.connect_activate(move |_, _| {
+            if let Some(doc) = mw.selected_document() {
+                save_document_as(&mw, &doc, |_| {});
+            }
+        });
+    }
+    window.add_action(&save_as_action);
+
+    // Export HTML
+    let export_html_action = gio::SimpleAction::new("export-html", None);
+    {
+        let mw = mw.clone();
+        export_html_action.connect_activate(move |_, _| {
+            if let Some(doc) = mw.selected_document() {
+                export_html_document(&mw, &doc);
+            }
+        });
+    }
+    window.add_action(&export_html_action);
+
+    // Export PDF
+    let export_pdf_action = gio::SimpleAction::new("export-pdf", None);
+    {
+        let mw = mw.clone();
+        export_pdf_action.connect_activate(move |_, _| {
+            let Some(doc) = mw.selected_document() else {
+                return;
+            };
+            export_pdf_document(&mw, &doc);
+        });
+    }
+    window.add_action(&export_pdf_action);
+
+    // Properties
+    let properties_action = gio::SimpleAction::new("properties", None);
+    {
+        let mw = mw.clone();
+        properties_action.connect_activate(move |_, _| {
+            if let Some(doc) = mw.selected_document() {
+                edit_properties(&mw, &doc);
+            }
+        });
+    }
+    window.add_action(&properties_action);
+
+    // Preferences
+    let preferences_action = gio::SimpleAction::new("preferences", None);
+    {
+        let mw = mw.clone();
+        preferences_action.connect_activate(move |_, _| {
+            let theme_model = gio::ListStore::new::<StringObject>();
+            theme_model.append(&StringObject::new("Auto"));
+            theme_model.append(&StringObject::new("Dark"));
+            theme_model.append(&StringObject::new("Light"));
+            let theme_expr = PropertyExpression::new(StringObject::static_type(), None::<&gtk4::Expression>, "string");
+            let theme_row = ComboRow::builder()
+                .title("Theme")
+                .subtitle("Application appearance")
+                .model(&theme_model)
+                .expression(&theme_expr)
+                .build();
+            let prefs = Preferences::default();
+            prefs
+                .bind_property("theme", &theme_row, "selected")
+                .transform_to(|_, theme: Theme| Some(theme as u32))
+                .transform_from(|_, selected: u32| {
+                    Some(match selected {
+                        1 => Theme::ForceDark,
+                        2 => Theme::ForceLight,
+                        _ => Theme::Default,
+                    })
+                })
+                .bidirectional()
+                .sync_create()
+                .build();
+
+            let scheme_mgr = sourceview5::StyleSchemeManager::default();
+            let all_ids: Vec<_> = scheme_mgr.scheme_ids();
+            let scheme_model = gio::ListStore::new::<StringObject>();
+            let mut scheme_ids = Vec::new();
+            for &pref_id in EDITOR_SCHEMES {
+                if let Some(id) = all_ids.iter().find(|s| s.as_str().eq_ignore_ascii_case(pref_id))
+                    && let Some(scheme) = scheme_mgr.scheme(id.as_str())
+                {
+                    scheme_model.append(&StringObject::new(&scheme.name()));
+                    scheme_ids.push(id.to_string());
+                }
+            }
+            if scheme_ids.is_empty() {
+                for id in all_ids.iter().take(10) {
+                    if let Some(scheme) = scheme_mgr.scheme(id.as_str()) {
+                        scheme_model.append(&StringObject::new(&scheme.name()));
+                        scheme_ids.push(id.to_string());
+                    }
+                }
+            }
+            if scheme_ids.is_empty() {
+                scheme_model.append(&StringObject::new("Adwaita dark"));
+                scheme_ids.push("Adwaita-dark".to_string());
+            }
+            let scheme_expr = PropertyExpression::new(StringObject::static_type(), None::<&gtk4::Expression>, "string");
+            let scheme_row = ComboRow::builder()
+                .title("Editor color scheme")
+                .subtitle("Syntax highlighting theme")
+                .model(&scheme_model)
+                .expression(&scheme_expr)
+                .build();
+            let ids = scheme_ids.clone();
+            prefs
+                .bind_property("color-scheme", &scheme_row, "selected")
+                .transform_to(move |_, id: String| {
+                    Some(ids.iter().position(|s| *s == id).unwrap_or(0) as u32)
+                })
+                .transform_from(move |_, selected: u32| scheme_ids.get(selected as usize).cloned())
+                .bidirectional()
+                .sync_create()
+                .build();
+
+            let appearance_group = PreferencesGroup::new();
+            appearance_group.set_title("Appearance");
+            appearance_group.add(&theme_row);
+            appearance_group.add(&scheme_row);
+            let appearance_page = PreferencesPage::builder()
+                .title("Appearance")
+                .icon_name("preferences-desktop-theme-symbolic")
+                .build();
+            appearance_page.add(&appearance_group);
+
+            let vim_row = SwitchRow::builder()
+                .title("Vim keybindings")
+                .subtitle("Use Vim-style keybindings in the editor")
+                .build();
+            let line_numbers_row = SwitchRow::builder()
+                .title("Show line numbers")
+                .subtitle("Display line numbers in the gutter")
+                .build();
+            let word_wrap_row = SwitchRow::builder()
+                .title("Word wrap")
+                .subtitle("Wrap long lines at word boundaries")
+                .build();
+            let sync_scroll_row = SwitchRow::builder()
+                .title("Synchronized scrolling")
+                .subtitle("Keep the preview scrolled to the part being edited")
+                .build();
+            let math_row = SwitchRow::builder()
+                .title("Math")
+                .subtitle("Typeset TeX between dollar signs; turn off for literal dollars")
+                .build();
+            let backup_row = SwitchRow::builder()
+                .title("Keep backup copies")
+                .subtitle("Save the previous version of a file as “name~”")
+                .build();
+            for (key, row) in [
+                ("vim", &vim_row),
+                ("line-numbers", &line_numbers_row),
+                ("word-wrap", &word_wrap_row),
+                ("sync-scroll", &sync_scroll_row),
+                ("math", &math_row),
+                ("backup", &backup_row),
+            ] {
+                prefs
+                    .bind_property(key, row, "active")
+                    .bidirectional()
+                    .sync_create()
+                    .build();
+            }
+
+            let editor_group = PreferencesGroup::new();
+            editor_group.add(&vim_row);
+            editor_group.add(&line_numbers_row);
+            editor_group.add(&word_wrap_row);
+            let files_group = PreferencesGroup::new();
+            files_group.set_title("Files");
+            files_group.add(&backup_row);
+            let preview_group = PreferencesGroup::new();
+            preview_group.set_title("Preview");
+            preview_group.add(&sync_scroll_row);
+            preview_group.add(&math_row);
+            let editor_page = PreferencesPage::builder()
+                .title("Editor")
+                .icon_name("accessories-text-editor-symbolic")
+                .build();
+            editor_page.add(&editor_group);
+            editor_page.add(&preview_group);
+            editor_page.add(&files_group);
+            let dialog = PreferencesDialog::builder()
+                .title("Preferences")
+                .build();
+            dialog.add(&appearance_page);
+            dialog.add(&editor_page);
+            dialog.present(Some(&mw.window));
+        });
+    }
+    window.add_action(&preferences_action);
+
+    mw.add_document();
+    // Files named on the command line replace the previous session's documents.
+    if files.is_empty() {
+        mw.restore_session(&session);
+    }
+    for file in files {
+        mw.open_file(file.clone(), viewer);
+    }
+
+    window.present();
+
+    // Only the first window of a process can find snapshots from a previous run.
+    static RECOVERY_CHECK: Once = Once::new();
+    RECOVERY_CHECK.call_once(|| offer_recovery(&mw));
+}